@@ -0,0 +1,356 @@
+//! Cellular automata in one or two dimensions, as used by days 12 and 18.
+//!
+//! Cells hold small integer states, which an [`Alphabet`] maps to and from the characters used in
+//! the puzzle inputs. Each generation every cell looks at the cells in its [`Neighbourhood`] and
+//! the [`Rules`] decide what it turns into. A [`History`] remembers every generation it has seen,
+//! which is enough to find cycles and extrapolate to any generation, even if the pattern moves.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+pub mod rules;
+
+pub type State = u8;
+
+/// The characters states are written as, the first one is state 0.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Alphabet(Vec<char>);
+
+impl Alphabet {
+    pub fn new(symbols: &str) -> Alphabet {
+        Alphabet(symbols.chars().collect())
+    }
+
+    pub fn state(&self, symbol: char) -> Result<State, String> {
+        self.0.iter()
+            .position(|&other| other == symbol)
+            .map(|state| state as State)
+            .ok_or_else(|| format!("unknown cell `{}`, expected one of `{}`", symbol, self.0.iter().collect::<String>()))
+    }
+
+    pub fn symbol(&self, state: State) -> char {
+        self.0[state as usize]
+    }
+}
+
+/// Offsets of the cells a cell looks at, in the order the rules get to see them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Neighbourhood {
+    offsets: Vec<(i64, i64)>,
+}
+
+impl Neighbourhood {
+    pub fn new(offsets: Vec<(i64, i64)>) -> Neighbourhood {
+        Neighbourhood { offsets }
+    }
+
+    /// The cell itself and `radius` cells to either side, from left to right.
+    pub fn line(radius: i64) -> Neighbourhood {
+        Neighbourhood::new((-radius..=radius).map(|dx| (dx, 0)).collect())
+    }
+
+    /// All cells within `radius` in both directions except for the cell itself, in reading order.
+    pub fn moore(radius: i64) -> Neighbourhood {
+        let offsets = (-radius..=radius)
+            .flat_map(|dy| (-radius..=radius).map(move |dx| (dx, dy)))
+            .filter(|&offset| offset != (0, 0))
+            .collect();
+
+        Neighbourhood::new(offsets)
+    }
+
+    /// How far the neighbourhood reaches horizontally and vertically.
+    fn reach(&self) -> (i64, i64) {
+        self.offsets.iter().fold((0, 0), |(x, y), &(dx, dy)| (x.max(dx.abs()), y.max(dy.abs())))
+    }
+}
+
+/// What lies beyond the edges of the grid.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Boundary {
+    /// The grid is a window onto an endless background of cells in this state. It grows and
+    /// shrinks with the pattern, so the pattern can move around freely.
+    Infinite(State),
+    /// Nothing, cells at the edges simply have fewer neighbours.
+    Fixed,
+}
+
+/// Decides the next state of a cell from its current state and those of its neighbours.
+pub trait Rules {
+    fn next_state(&self, centre: State, neighbours: &[State]) -> State;
+}
+
+/// A rectangular patch of cells. `origin` holds the coordinates of the top left cell.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Grid {
+    pub width: usize,
+    pub height: usize,
+    pub cells: Vec<State>,
+    pub origin: (i64, i64),
+}
+
+impl Grid {
+    pub fn parse(lines: &[&str], alphabet: &Alphabet) -> Result<Grid, String> {
+        let width = lines.first().map_or(0, |line| line.chars().count());
+        let mut cells = Vec::new();
+
+        for line in lines {
+            if line.chars().count() != width {
+                return Err(format!("all rows have to be {} cells wide: `{}`", width, line));
+            }
+
+            for symbol in line.chars() {
+                cells.push(alphabet.state(symbol)?);
+            }
+        }
+
+        Ok(Grid { width, height: lines.len(), cells, origin: (0, 0) })
+    }
+
+    /// State at the given coordinates, if they're on the grid.
+    pub fn get(&self, x: i64, y: i64) -> Option<State> {
+        let (x, y) = (x - self.origin.0, y - self.origin.1);
+        if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height {
+            return None;
+        }
+
+        Some(self.cells[y as usize * self.width + x as usize])
+    }
+
+    pub fn count(&self, state: State) -> usize {
+        self.cells.iter().filter(|&&cell| cell == state).count()
+    }
+
+    /// Coordinates of all cells in the given state, relative to the top left cell.
+    pub fn positions(&self, state: State) -> impl Iterator<Item=(usize, usize)> + '_ {
+        let width = self.width;
+        self.cells.iter()
+            .enumerate()
+            .filter(move |&(_, &cell)| cell == state)
+            .map(move |(index, _)| (index % width, index / width))
+    }
+
+    pub fn render(&self, alphabet: &Alphabet) -> String {
+        self.cells.chunks(self.width.max(1))
+            .map(|row| row.iter().map(|&state| alphabet.symbol(state)).collect::<String>())
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    /// Drops rows and columns on the edges which only contain `background`.
+    fn trim(&mut self, background: State) {
+        let occupied: Vec<(usize, usize)> = self.cells.iter()
+            .enumerate()
+            .filter(|&(_, &cell)| cell != background)
+            .map(|(index, _)| (index % self.width, index / self.width))
+            .collect();
+
+        if occupied.is_empty() {
+            *self = Grid { width: 0, height: 0, cells: Vec::new(), origin: (0, 0) };
+            return;
+        }
+
+        let min_x = occupied.iter().map(|pos| pos.0).min().unwrap();
+        let max_x = occupied.iter().map(|pos| pos.0).max().unwrap();
+        let min_y = occupied.iter().map(|pos| pos.1).min().unwrap();
+        let max_y = occupied.iter().map(|pos| pos.1).max().unwrap();
+
+        if (min_x, min_y, max_x + 1, max_y + 1) == (0, 0, self.width, self.height) {
+            return;
+        }
+
+        let width = max_x + 1 - min_x;
+        let cells = (min_y..=max_y)
+            .flat_map(|y| self.cells[y * self.width + min_x..y * self.width + max_x + 1].to_vec())
+            .collect();
+
+        *self = Grid {
+            width,
+            height: max_y + 1 - min_y,
+            cells,
+            origin: (self.origin.0 + min_x as i64, self.origin.1 + min_y as i64),
+        };
+    }
+
+    fn fingerprint(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        (self.width, self.height, &self.cells).hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Same cells, wherever they are.
+    fn same_pattern(&self, other: &Grid) -> bool {
+        (self.width, self.height, &self.cells) == (other.width, other.height, &other.cells)
+    }
+}
+
+pub struct Automaton<R: Rules> {
+    pub grid: Grid,
+    pub generation: u64,
+    neighbourhood: Neighbourhood,
+    rules: R,
+    boundary: Boundary,
+    /// The grid the next generation is written to, so stepping doesn't allocate.
+    next: Vec<State>,
+    neighbours: Vec<State>,
+}
+
+impl<R: Rules> Automaton<R> {
+    pub fn new(grid: Grid, neighbourhood: Neighbourhood, rules: R, boundary: Boundary) -> Result<Automaton<R>, String> {
+        if let Boundary::Infinite(background) = boundary {
+            let empty = vec![background; neighbourhood.offsets.len()];
+            if rules.next_state(background, &empty) != background {
+                return Err("the rules turn the empty background into something else, the pattern would be infinite".to_string());
+            }
+        }
+
+        let mut automaton = Automaton { grid, generation: 0, neighbourhood, rules, boundary, next: Vec::new(), neighbours: Vec::new() };
+        if let Boundary::Infinite(background) = boundary {
+            automaton.grid.trim(background);
+        }
+
+        Ok(automaton)
+    }
+
+    pub fn step(&mut self) {
+        // on an infinite background the pattern can grow by as far as the neighbourhood reaches
+        let (grow_x, grow_y) = match self.boundary {
+            Boundary::Infinite(_) => self.neighbourhood.reach(),
+            Boundary::Fixed => (0, 0),
+        };
+        let width = self.grid.width + 2 * grow_x as usize;
+        let height = self.grid.height + 2 * grow_y as usize;
+        let origin = (self.grid.origin.0 - grow_x, self.grid.origin.1 - grow_y);
+
+        self.next.clear();
+        for y in 0..height as i64 {
+            for x in 0..width as i64 {
+                let (x, y) = (origin.0 + x, origin.1 + y);
+
+                self.neighbours.clear();
+                for &(dx, dy) in self.neighbourhood.offsets.iter() {
+                    match (self.grid.get(x + dx, y + dy), self.boundary) {
+                        (Some(state), _) => self.neighbours.push(state),
+                        (None, Boundary::Infinite(background)) => self.neighbours.push(background),
+                        (None, Boundary::Fixed) => {}
+                    }
+                }
+
+                let centre = match (self.grid.get(x, y), self.boundary) {
+                    (Some(state), _) => state,
+                    (None, Boundary::Infinite(background)) => background,
+                    (None, Boundary::Fixed) => unreachable!("fixed grids don't grow"),
+                };
+                self.next.push(self.rules.next_state(centre, &self.neighbours));
+            }
+        }
+
+        std::mem::swap(&mut self.grid.cells, &mut self.next);
+        self.grid.width = width;
+        self.grid.height = height;
+        self.grid.origin = origin;
+        if let Boundary::Infinite(background) = self.boundary {
+            self.grid.trim(background);
+        }
+
+        self.generation += 1;
+    }
+}
+
+/// The generations from `start` on repeat every `period` generations, moved by `drift` each time.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cycle {
+    pub start: u64,
+    pub period: u64,
+    pub drift: (i64, i64),
+}
+
+/// A generation's grid together with where it actually is, which can be very far away.
+pub struct Snapshot<'a> {
+    pub grid: &'a Grid,
+    pub origin: (i128, i128),
+}
+
+impl<'a> Snapshot<'a> {
+    /// Coordinates of all cells in the given state.
+    pub fn positions(&self, state: State) -> impl Iterator<Item=(i128, i128)> + 'a {
+        let origin = self.origin;
+        self.grid.positions(state).map(move |(x, y)| (origin.0 + x as i128, origin.1 + y as i128))
+    }
+}
+
+/// Runs an automaton while remembering every generation, until the generations start to repeat.
+pub struct History<R: Rules> {
+    automaton: Automaton<R>,
+    generations: Vec<Grid>,
+    /// Generations by the fingerprint of their pattern.
+    seen: HashMap<u64, Vec<u64>>,
+    cycle: Option<Cycle>,
+}
+
+impl<R: Rules> History<R> {
+    pub fn new(automaton: Automaton<R>) -> History<R> {
+        let mut history = History { generations: Vec::new(), seen: HashMap::new(), cycle: None, automaton };
+        history.record();
+        history
+    }
+
+    fn record(&mut self) {
+        let grid = self.automaton.grid.clone();
+        let generation = self.automaton.generation;
+
+        let generations = &self.generations;
+        let candidates = self.seen.entry(grid.fingerprint()).or_default();
+        let earlier = candidates.iter().cloned().find(|&earlier| generations[earlier as usize].same_pattern(&grid));
+
+        match earlier {
+            Some(start) => {
+                let first = &self.generations[start as usize];
+                self.cycle = Some(Cycle {
+                    start,
+                    period: generation - start,
+                    drift: (grid.origin.0 - first.origin.0, grid.origin.1 - first.origin.1),
+                });
+            }
+            None => {
+                candidates.push(generation);
+                self.generations.push(grid);
+            }
+        }
+    }
+
+    /// Steps until the generations repeat, giving up after `limit` generations.
+    pub fn find_cycle(&mut self, limit: u64) -> Option<Cycle> {
+        while self.cycle.is_none() && self.automaton.generation < limit {
+            self.automaton.step();
+            self.record();
+        }
+
+        self.cycle
+    }
+
+    /// The grid at any generation, running the automaton further if needed. Without a cycle
+    /// within `limit` generations, generations past that can't be told.
+    pub fn at(&mut self, generation: u64, limit: u64) -> Result<Snapshot<'_>, String> {
+        if self.find_cycle(generation.min(limit)).is_none() && generation >= self.generations.len() as u64 {
+            return Err(format!("the automaton doesn't repeat within {} generations", limit));
+        }
+
+        if let Some(grid) = self.generations.get(generation as usize) {
+            return Ok(Snapshot { grid, origin: (grid.origin.0 as i128, grid.origin.1 as i128) });
+        }
+
+        let cycle = self.cycle.unwrap();
+        let repeats = ((generation - cycle.start) / cycle.period) as i128;
+        let grid = &self.generations[(cycle.start + (generation - cycle.start) % cycle.period) as usize];
+
+        Ok(Snapshot {
+            grid,
+            origin: (
+                grid.origin.0 as i128 + repeats * cycle.drift.0 as i128,
+                grid.origin.1 as i128 + repeats * cycle.drift.1 as i128,
+            ),
+        })
+    }
+}
@@ -0,0 +1,186 @@
+//! The two ways of writing down rules the puzzles need.
+
+use std::collections::HashMap;
+
+use super::{Alphabet, Rules, State};
+
+/// One rule per neighbourhood, written as in day 12: `#.#.. => #`.
+///
+/// The pattern lists the neighbours in the neighbourhood's order. Neighbourhoods without a rule
+/// turn into the default state, if there is one.
+pub struct PatternRules {
+    patterns: HashMap<Vec<State>, State>,
+    default: Option<State>,
+}
+
+impl PatternRules {
+    pub fn parse(lines: &[&str], alphabet: &Alphabet, default: Option<State>) -> Result<PatternRules, String> {
+        let mut patterns = HashMap::new();
+        let mut size = None;
+
+        for line in lines {
+            let (pattern, result) = match line.split(" => ").collect::<Vec<&str>>()[..] {
+                [pattern, result] => (pattern.trim(), result.trim()),
+                _ => return Err(format!("expected `pattern => result`, got `{}`", line))
+            };
+
+            let pattern = pattern.chars().map(|symbol| alphabet.state(symbol)).collect::<Result<Vec<State>, String>>()?;
+            let result = match result.chars().collect::<Vec<char>>()[..] {
+                [symbol] => alphabet.state(symbol)?,
+                _ => return Err(format!("the result of `{}` has to be a single cell", line))
+            };
+
+            if *size.get_or_insert(pattern.len()) != pattern.len() {
+                return Err(format!("all patterns have to be {} cells long: `{}`", size.unwrap(), line));
+            }
+
+            if patterns.insert(pattern, result).is_some() {
+                return Err(format!("the pattern of `{}` appears more than once", line));
+            }
+        }
+
+        Ok(PatternRules { patterns, default })
+    }
+}
+
+impl Rules for PatternRules {
+    fn next_state(&self, _centre: State, neighbours: &[State]) -> State {
+        match (self.patterns.get(neighbours), self.default) {
+            (Some(&result), _) => result,
+            (None, Some(default)) => default,
+            (None, None) => panic!("Rules didn't include pattern {:?}", neighbours)
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Comparison {
+    Less,
+    LessOrEqual,
+    Equal,
+    GreaterOrEqual,
+    Greater,
+}
+
+impl Comparison {
+    const ALL: [(&'static str, Comparison); 5] = [
+        ("<=", Comparison::LessOrEqual),
+        (">=", Comparison::GreaterOrEqual),
+        ("==", Comparison::Equal),
+        ("<", Comparison::Less),
+        (">", Comparison::Greater),
+    ];
+
+    fn holds(self, a: usize, b: usize) -> bool {
+        match self {
+            Comparison::Less => a < b,
+            Comparison::LessOrEqual => a <= b,
+            Comparison::Equal => a == b,
+            Comparison::GreaterOrEqual => a >= b,
+            Comparison::Greater => a > b,
+        }
+    }
+}
+
+/// `| >= 3`: at least three of the neighbours are in the `|` state.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Condition {
+    state: State,
+    comparison: Comparison,
+    count: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct CountedRule {
+    /// `None` for `*`, which applies to any state.
+    from: Option<State>,
+    to: State,
+    conditions: Vec<Condition>,
+}
+
+/// Rules that only care about how many neighbours are in which state, one per line:
+///
+/// ```text
+/// . -> | if | >= 3
+/// # -> # if # >= 1 and | >= 1
+/// # -> .
+/// ```
+///
+/// The first rule for the cell's state whose conditions all hold decides, cells without such a
+/// rule stay as they are.
+pub struct CountedRules {
+    rules: Vec<CountedRule>,
+}
+
+impl CountedRules {
+    pub fn parse(text: &str, alphabet: &Alphabet) -> Result<CountedRules, String> {
+        let rules = text.lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty())
+            .map(|line| CountedRules::parse_rule(line, alphabet).map_err(|e| format!("rule `{}`: {}", line, e)))
+            .collect::<Result<Vec<CountedRule>, String>>()?;
+
+        Ok(CountedRules { rules })
+    }
+
+    fn parse_state(word: &str, alphabet: &Alphabet) -> Result<State, String> {
+        match word.chars().collect::<Vec<char>>()[..] {
+            [symbol] => alphabet.state(symbol),
+            _ => Err(format!("expected a single cell, got `{}`", word))
+        }
+    }
+
+    fn parse_rule(line: &str, alphabet: &Alphabet) -> Result<CountedRule, String> {
+        let (transition, conditions) = match line.find(" if ") {
+            Some(split) => (&line[..split], Some(&line[split + 4..])),
+            None => (line, None)
+        };
+
+        let (from, to) = match transition.split("->").map(|word| word.trim()).collect::<Vec<&str>>()[..] {
+            ["*", to] => (None, CountedRules::parse_state(to, alphabet)?),
+            [from, to] => (Some(CountedRules::parse_state(from, alphabet)?), CountedRules::parse_state(to, alphabet)?),
+            _ => return Err("expected `from -> to`".to_string())
+        };
+
+        let conditions = match conditions {
+            Some(conditions) => conditions.split(" and ")
+                .map(|condition| CountedRules::parse_condition(condition.trim(), alphabet))
+                .collect::<Result<Vec<Condition>, String>>()?,
+            None => Vec::new()
+        };
+
+        Ok(CountedRule { from, to, conditions })
+    }
+
+    fn parse_condition(condition: &str, alphabet: &Alphabet) -> Result<Condition, String> {
+        let error = || format!("expected a condition like `| >= 3`, got `{}`", condition);
+
+        let parts: Vec<&str> = condition.split_whitespace().collect();
+        let (state, comparison, count) = match parts[..] {
+            [state, comparison, count] => (state, comparison, count),
+            _ => return Err(error())
+        };
+
+        let comparison = Comparison::ALL.iter()
+            .find(|&&(symbol, _)| symbol == comparison)
+            .map(|&(_, comparison)| comparison)
+            .ok_or_else(error)?;
+
+        Ok(Condition {
+            state: CountedRules::parse_state(state, alphabet)?,
+            comparison,
+            count: count.parse().map_err(|_| error())?,
+        })
+    }
+}
+
+impl Rules for CountedRules {
+    fn next_state(&self, centre: State, neighbours: &[State]) -> State {
+        let count = |state| neighbours.iter().filter(|&&other| other == state).count();
+
+        self.rules.iter()
+            .filter(|rule| rule.from.is_none_or(|from| from == centre))
+            .find(|rule| rule.conditions.iter().all(|condition| condition.comparison.holds(count(condition.state), condition.count)))
+            .map_or(centre, |rule| rule.to)
+    }
+}
@@ -0,0 +1,337 @@
+//! Assembler for a slightly friendlier dialect of ElfCode.
+//!
+//! On top of the plain `op a b c` lines the assembler understands:
+//!
+//! * `; comment` until the end of the line
+//! * `#ip <register>` to bind the instruction pointer (also defines the alias `ip`)
+//! * `#alias <name> <register>` to give a register a name
+//! * `label:` in front of an instruction (or on a line of its own)
+//! * labels and `label+n` / `label-n` expressions wherever an immediate is expected
+//! * `_` for operands the opcode ignores
+//! * `jmp label` as a shorthand for `seti label-1 0 ip`
+//!
+//! Registers can be written as plain numbers, `r0` to `r5` or by their alias.
+//! The output is the numeric program format read by days 19 and 21.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use super::{Instruction, Opcode, Operand, Program, REGISTER_COUNT};
+
+#[derive(Debug, PartialEq)]
+pub struct AssemblyError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AssemblyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// A source line which still has to be turned into an instruction once all labels are known.
+struct PendingInstruction<'a> {
+    line: usize,
+    mnemonic: &'a str,
+    operands: Vec<&'a str>,
+}
+
+fn strip_comment(line: &str) -> &str {
+    match line.find(';') {
+        Some(index) => &line[..index],
+        None => line
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => chars.all(|c| c.is_ascii_alphanumeric() || c == '_'),
+        _ => false
+    }
+}
+
+struct Assembler<'a> {
+    ip: Option<usize>,
+    aliases: HashMap<&'a str, usize>,
+    labels: HashMap<&'a str, usize>,
+    pending: Vec<PendingInstruction<'a>>,
+    errors: Vec<AssemblyError>,
+}
+
+impl<'a> Assembler<'a> {
+    fn new() -> Assembler<'a> {
+        Assembler { ip: None, aliases: HashMap::new(), labels: HashMap::new(), pending: Vec::new(), errors: Vec::new() }
+    }
+
+    fn error(&mut self, line: usize, message: String) {
+        self.errors.push(AssemblyError { line, message });
+    }
+
+    fn resolve_register(&self, raw: &str) -> Result<usize, String> {
+        let register = if let Some(&register) = self.aliases.get(raw) {
+            register
+        } else {
+            raw.strip_prefix('r').unwrap_or(raw).parse().map_err(|_| format!("unknown register `{}`", raw))?
+        };
+
+        if register >= REGISTER_COUNT {
+            return Err(format!("register {} is out of range (0 - {})", register, REGISTER_COUNT - 1));
+        }
+
+        Ok(register)
+    }
+
+    fn resolve_term(&self, raw: &str) -> Result<i64, String> {
+        if let Ok(value) = raw.parse() {
+            Ok(value)
+        } else if let Some(&address) = self.labels.get(raw) {
+            Ok(address as i64)
+        } else if is_identifier(raw) {
+            Err(format!("undefined label `{}`", raw))
+        } else {
+            Err(format!("invalid immediate `{}`", raw))
+        }
+    }
+
+    fn resolve_immediate(&self, raw: &str) -> Result<usize, String> {
+        if raw == "_" {
+            return Ok(0);
+        }
+
+        let mut value: i64 = 0;
+        let mut sign = 1;
+        let mut start = 0;
+
+        for (index, c) in raw.char_indices().chain(Some((raw.len(), '+'))) {
+            if (c == '+' || c == '-') && index > start {
+                value = self.resolve_term(&raw[start..index])?.checked_mul(sign)
+                    .and_then(|term| value.checked_add(term))
+                    .ok_or_else(|| format!("`{}` is too large", raw))?;
+                sign = if c == '+' { 1 } else { -1 };
+                start = index + 1;
+            }
+        }
+
+        if start <= raw.len() || value < 0 {
+            return Err(format!("`{}` doesn't evaluate to a non-negative number", raw));
+        }
+
+        Ok(value as usize)
+    }
+
+    fn resolve_operand(&self, kind: Operand, raw: &str) -> Result<usize, String> {
+        match kind {
+            Operand::Register => self.resolve_register(raw),
+            Operand::Immediate | Operand::Ignored => self.resolve_immediate(raw)
+        }
+    }
+
+    fn directive(&mut self, line: usize, parts: &[&'a str]) {
+        let result = match parts {
+            ["#ip", register] => self.resolve_register(register).map(|register| {
+                self.ip = Some(register);
+                self.aliases.insert("ip", register);
+            }),
+            ["#alias", name, register] if is_identifier(name) => self.resolve_register(register).map(|register| {
+                self.aliases.insert(name, register);
+            }),
+            _ => Err(format!("malformed directive `{}`", parts.join(" ")))
+        };
+
+        if let Err(message) = result {
+            self.error(line, message);
+        }
+    }
+
+    /// First pass: directives, labels and the raw instruction text.
+    fn scan(&mut self, source: &'a str) {
+        for (index, raw_line) in source.lines().enumerate() {
+            let line = index + 1;
+            let mut text = strip_comment(raw_line).trim();
+
+            if text.starts_with('#') {
+                let parts: Vec<&str> = text.split_whitespace().collect();
+                self.directive(line, &parts);
+                continue;
+            }
+
+            if let Some(colon) = text.find(':') {
+                let label = text[..colon].trim();
+                if !is_identifier(label) {
+                    self.error(line, format!("invalid label name `{}`", label));
+                } else if self.labels.contains_key(label) {
+                    self.error(line, format!("label `{}` is defined twice", label));
+                } else {
+                    self.labels.insert(label, self.pending.len());
+                }
+
+                text = text[colon + 1..].trim();
+            }
+
+            let mut parts = text.split_whitespace();
+            if let Some(mnemonic) = parts.next() {
+                self.pending.push(PendingInstruction { line, mnemonic, operands: parts.collect() });
+            }
+        }
+    }
+
+    fn encode(&self, pending: &PendingInstruction) -> Result<Instruction, String> {
+        if pending.mnemonic == "jmp" {
+            let ip = self.ip.ok_or("`jmp` requires an `#ip` directive")?;
+            return match pending.operands[..] {
+                [target] => {
+                    let value = self.resolve_immediate(target)?
+                        .checked_sub(1)
+                        .ok_or("can't jump to the first instruction with `jmp`")?;
+                    Ok(Instruction::new(Opcode::Seti, value, 0, ip))
+                }
+                _ => Err("`jmp` takes exactly one operand".to_string())
+            };
+        }
+
+        let opcode = Opcode::from_mnemonic(pending.mnemonic)
+            .ok_or_else(|| format!("unknown opcode `{}`", pending.mnemonic))?;
+
+        if let [a, b, c] = pending.operands[..] {
+            let (kind_a, kind_b) = opcode.operands();
+            Ok(Instruction::new(
+                opcode,
+                self.resolve_operand(kind_a, a)?,
+                self.resolve_operand(kind_b, b)?,
+                self.resolve_register(c)?,
+            ))
+        } else {
+            Err(format!("`{}` takes exactly three operands", opcode))
+        }
+    }
+}
+
+/// Assembles the source, collecting every error instead of stopping at the first one.
+pub fn assemble(source: &str) -> Result<Program, Vec<AssemblyError>> {
+    let mut assembler = Assembler::new();
+    assembler.scan(source);
+
+    if assembler.ip.is_none() {
+        assembler.error(1, "missing `#ip` directive".to_string());
+    }
+
+    let mut instructions = Vec::new();
+    for pending in assembler.pending.iter() {
+        match assembler.encode(pending) {
+            Ok(instruction) => instructions.push(instruction),
+            Err(message) => assembler.errors.push(AssemblyError { line: pending.line, message })
+        }
+    }
+
+    if assembler.errors.is_empty() {
+        Ok(Program { ip: assembler.ip.unwrap(), instructions })
+    } else {
+        assembler.errors.sort_by_key(|e| e.line);
+        Err(assembler.errors)
+    }
+}
+
+/// Registers the disassembler declares an alias for are named by letter, any others by number.
+fn register_name(register: usize, ip: usize) -> String {
+    if register == ip {
+        "ip".to_string()
+    } else if register < REGISTER_COUNT {
+        ((b'a' + register as u8) as char).to_string()
+    } else {
+        format!("r{}", register)
+    }
+}
+
+fn label_name(address: usize) -> String {
+    format!("l{}", address)
+}
+
+/// Address an instruction jumps to if it writes a constant to the ip register.
+fn constant_jump_target(index: usize, instruction: &Instruction, ip: usize) -> Option<usize> {
+    if instruction.c != ip {
+        return None;
+    }
+
+    match instruction.opcode {
+        Opcode::Seti => instruction.a.checked_add(1),
+        Opcode::Addi if instruction.a == ip => index.checked_add(instruction.b)?.checked_add(1),
+        _ => None
+    }
+}
+
+/// Pseudo-code for a single instruction, in the style of the notes at the bottom of day 19.
+pub fn decompile_instruction(index: usize, instruction: &Instruction, ip: usize) -> String {
+    if let Some(target) = constant_jump_target(index, instruction, ip) {
+        return format!("goto {}", target);
+    }
+
+    let (kind_a, kind_b) = instruction.opcode.operands();
+    let show = |kind, value| match kind {
+        Operand::Register => register_name(value, ip),
+        _ => value.to_string()
+    };
+
+    let target = register_name(instruction.c, ip);
+    let expression = match kind_b {
+        Operand::Ignored => show(kind_a, instruction.a),
+        _ => format!("{} {} {}", show(kind_a, instruction.a), instruction.opcode.symbol(), show(kind_b, instruction.b))
+    };
+
+    if instruction.c == ip {
+        format!("goto ({}) + 1", expression)
+    } else {
+        format!("{} = {}", target, expression)
+    }
+}
+
+/// Turns a numeric program into assembler source which assembles back into the exact same program.
+pub fn disassemble(program: &Program) -> String {
+    let ip = program.ip;
+    let mut labels: Vec<usize> = program.instructions.iter()
+        .enumerate()
+        .filter(|&(_, instruction)| instruction.opcode == Opcode::Seti)
+        .filter_map(|(index, instruction)| constant_jump_target(index, instruction, ip))
+        .filter(|&target| target <= program.instructions.len())
+        .collect();
+    labels.sort();
+    labels.dedup();
+
+    let mut lines = vec![format!("#ip {}", ip)];
+    for register in (0..REGISTER_COUNT).filter(|&r| r != ip) {
+        lines.push(format!("#alias {} {}", register_name(register, ip), register));
+    }
+    lines.push(String::new());
+
+    for (index, instruction) in program.instructions.iter().enumerate() {
+        if labels.contains(&index) {
+            lines.push(format!("{}:", label_name(index)));
+        }
+
+        let (kind_a, kind_b) = instruction.opcode.operands();
+        let jump_target = constant_jump_target(index, instruction, ip)
+            .filter(|&target| instruction.opcode == Opcode::Seti && labels.contains(&target));
+
+        let code = match jump_target {
+            Some(target) if instruction.b == 0 => format!("jmp {}", label_name(target)),
+            Some(target) => format!("seti {}-1 {} ip", label_name(target), instruction.b),
+            None => {
+                let show = |kind, value: usize| match kind {
+                    Operand::Register => register_name(value, ip),
+                    _ => value.to_string()
+                };
+
+                format!("{} {} {} {}", instruction.opcode, show(kind_a, instruction.a), show(kind_b, instruction.b), register_name(instruction.c, ip))
+            }
+        };
+
+        lines.push(format!("    {:<20} ; {:>2}: {}", code, index, decompile_instruction(index, instruction, ip)));
+    }
+
+    if labels.contains(&program.instructions.len()) {
+        lines.push(format!("{}:", label_name(program.instructions.len())));
+    }
+
+    lines.join("\n")
+}
@@ -0,0 +1,222 @@
+//! Control-flow graph of an ElfCode program.
+//!
+//! Basic blocks end at every instruction that writes the ip register and start at every address
+//! such an instruction can jump to. A jump whose target depends on a register which was last set
+//! by a comparison in the same block is treated as a two-way branch, anything else we can't
+//! figure out statically goes to a single "computed" node.
+
+extern crate petgraph;
+
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+
+use self::petgraph::graph::{Graph, NodeIndex};
+use self::petgraph::visit::EdgeRef;
+
+use super::assembler::decompile_instruction;
+use super::{Instruction, Opcode, Operand, Program};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Node {
+    Block { start: usize, end: usize },
+    Halt,
+    Computed,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Edge {
+    Fallthrough,
+    Jump,
+    Branch,
+    Computed,
+}
+
+impl Edge {
+    fn name(self) -> &'static str {
+        match self {
+            Edge::Fallthrough => "fallthrough",
+            Edge::Jump => "jump",
+            Edge::Branch => "branch",
+            Edge::Computed => "computed",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Targets {
+    Known(Edge, Vec<usize>),
+    Unknown,
+}
+
+fn is_comparison(opcode: Opcode) -> bool {
+    matches!(opcode, Opcode::Gtir | Opcode::Gtri | Opcode::Gtrr | Opcode::Eqir | Opcode::Eqri | Opcode::Eqrr)
+}
+
+/// Values an operand can have when the instruction at `address` is executed, if we can tell.
+fn operand_values(program: &Program, leaders: &BTreeSet<usize>, address: usize, kind: Operand, operand: usize) -> Option<Vec<usize>> {
+    match kind {
+        Operand::Immediate => return Some(vec![operand]),
+        Operand::Ignored => return Some(vec![0]),
+        Operand::Register if operand == program.ip => return Some(vec![address]),
+        Operand::Register => {}
+    }
+
+    // at the start of a block the register can come from any predecessor
+    if leaders.contains(&address) {
+        return None;
+    }
+
+    // look for the last write to the register within the block
+    for previous in (0..address).rev() {
+        let instruction = &program.instructions[previous];
+        if instruction.c == operand {
+            return if is_comparison(instruction.opcode) { Some(vec![0, 1]) } else { None };
+        }
+
+        if leaders.contains(&previous) {
+            break;
+        }
+    }
+
+    None
+}
+
+fn jump_targets(program: &Program, leaders: &BTreeSet<usize>, address: usize, instruction: &Instruction) -> Targets {
+    let (kind_a, kind_b) = instruction.opcode.operands();
+    let values_a = operand_values(program, leaders, address, kind_a, instruction.a);
+    let values_b = operand_values(program, leaders, address, kind_b, instruction.b);
+
+    match (values_a, values_b) {
+        (Some(values_a), Some(values_b)) => {
+            let mut targets: Vec<usize> = values_a.iter()
+                .flat_map(|&a| values_b.iter().map(move |&b| instruction.opcode.evaluate(a, b).wrapping_add(1)))
+                .collect();
+            targets.sort();
+            targets.dedup();
+
+            let edge = if targets.len() == 1 { Edge::Jump } else { Edge::Branch };
+            Targets::Known(edge, targets)
+        }
+        _ => Targets::Unknown
+    }
+}
+
+pub struct ControlFlowGraph {
+    pub graph: Graph<Node, Edge>,
+}
+
+impl ControlFlowGraph {
+    pub fn build(program: &Program) -> ControlFlowGraph {
+        let length = program.instructions.len();
+        let writes_ip: Vec<usize> = (0..length)
+            .filter(|&address| program.instructions[address].c == program.ip)
+            .collect();
+
+        // Knowing more leaders can change what we know about branches, so repeat until nothing changes.
+        let mut leaders: BTreeSet<usize> = Some(0).into_iter()
+            .chain(writes_ip.iter().map(|&address| address + 1))
+            .filter(|&address| address < length)
+            .collect();
+        let mut targets: HashMap<usize, Targets>;
+
+        loop {
+            targets = writes_ip.iter()
+                .map(|&address| (address, jump_targets(program, &leaders, address, &program.instructions[address])))
+                .collect();
+
+            let before = leaders.len();
+            for target in targets.values() {
+                if let Targets::Known(_, addresses) = target {
+                    leaders.extend(addresses.iter().filter(|&&address| address < length));
+                }
+            }
+
+            if leaders.len() == before {
+                break;
+            }
+        }
+
+        let mut graph = Graph::new();
+        let mut blocks: HashMap<usize, NodeIndex> = HashMap::new();
+        let starts: Vec<usize> = leaders.iter().cloned().collect();
+
+        for (index, &start) in starts.iter().enumerate() {
+            let end = starts.get(index + 1).map_or(length, |&next| next) - 1;
+            blocks.insert(start, graph.add_node(Node::Block { start, end }));
+        }
+
+        let halt = graph.add_node(Node::Halt);
+        let computed = graph.add_node(Node::Computed);
+        let node_at = |address: usize| if address < length { blocks[&address] } else { halt };
+
+        for (index, &start) in starts.iter().enumerate() {
+            let end = starts.get(index + 1).map_or(length, |&next| next) - 1;
+            let block = blocks[&start];
+
+            match targets.get(&end) {
+                Some(Targets::Known(edge, addresses)) => {
+                    for &address in addresses.iter() {
+                        graph.update_edge(block, node_at(address), *edge);
+                    }
+                }
+                Some(Targets::Unknown) => {
+                    graph.update_edge(block, computed, Edge::Computed);
+                }
+                None => {
+                    graph.update_edge(block, node_at(end + 1), Edge::Fallthrough);
+                }
+            }
+        }
+
+        // drop the computed node again if nothing needs it
+        if graph.neighbors_directed(computed, petgraph::Direction::Incoming).next().is_none() {
+            graph.remove_node(computed);
+        }
+
+        ControlFlowGraph { graph }
+    }
+
+    pub fn to_dot(&self, program: &Program) -> String {
+        let name = |node: NodeIndex| match self.graph[node] {
+            Node::Block { start, .. } => format!("b{}", start),
+            Node::Halt => "halt".to_string(),
+            Node::Computed => "computed".to_string(),
+        };
+
+        let mut lines = vec![
+            "digraph elfcode {".to_string(),
+            "    node [shape=box, fontname=\"monospace\"];".to_string(),
+        ];
+
+        for node in self.graph.node_indices() {
+            let label = match self.graph[node] {
+                Node::Block { start, end } => (start..=end)
+                    .map(|address| {
+                        let instruction = &program.instructions[address];
+                        format!("{:>2}: {:<16} {}\\l", address, instruction.to_string(), decompile_instruction(address, instruction, program.ip))
+                    })
+                    .collect::<String>(),
+                Node::Halt => "halt".to_string(),
+                Node::Computed => "computed jump".to_string(),
+            };
+            let shape = match self.graph[node] {
+                Node::Block { .. } => "",
+                _ => ", shape=ellipse",
+            };
+
+            lines.push(format!("    {} [label=\"{}\"{}];", name(node), label, shape));
+        }
+
+        for edge in self.graph.edge_references() {
+            let style = match *edge.weight() {
+                Edge::Fallthrough => "",
+                Edge::Computed => ", style=dashed",
+                _ => ", style=bold",
+            };
+            lines.push(format!("    {} -> {} [label=\"{}\"{}];", name(edge.source()), name(edge.target()), edge.weight().name(), style));
+        }
+
+        lines.push("}".to_string());
+        lines.join("\n")
+    }
+}
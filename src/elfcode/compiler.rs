@@ -0,0 +1,352 @@
+//! Optimising execution engine.
+//!
+//! The program is decoded once into a compact array in which every read of the ip register is
+//! replaced by the (statically known) address of the instruction, and every write to it becomes
+//! a jump. On top of that a handful of loop idioms found in the puzzle inputs are recognised and
+//! executed with native arithmetic. The engine always ends up in exactly the same state as
+//! [`interpreter::run`](../interpreter/fn.run.html), including the number of executed instructions.
+
+use std::cmp;
+
+use super::{Arithmetic, FaultKind, Instruction, Opcode, Operand, Program, Registers, Stop};
+
+#[derive(Clone, Copy, Debug)]
+enum Value {
+    Register(usize),
+    Constant(usize),
+}
+
+impl Value {
+    fn get(self, registers: &Registers) -> usize {
+        match self {
+            Value::Register(register) => registers[register],
+            Value::Constant(value) => value
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Decoded {
+    Compute { opcode: Opcode, a: Value, b: Value, c: usize },
+    Jump(usize),
+    ComputedJump { opcode: Opcode, a: Value, b: Value },
+    Fault(FaultKind),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Idiom {
+    /// `f` counts up from its current value until `(f + 1) * k > d`, i.e. `f = d / k`.
+    ///
+    /// ```text
+    /// s+0: addi f 1 t
+    /// s+1: muli t k t
+    /// s+2: gtrr t d t
+    /// s+3: addr t ip ip
+    /// s+4: addi ip 1 ip
+    /// s+5: seti exit-1 _ ip
+    /// s+6: addi f 1 f
+    /// s+7: seti s-1 _ ip
+    /// ```
+    DivisionLoop { f: usize, t: usize, d: usize, k: usize, exit: usize },
+    /// Adds `e` to `a` if `e` divides `c`, trying every `f` up to `c`.
+    ///
+    /// ```text
+    /// s+0: mulr e f b
+    /// s+1: eqrr b c b
+    /// s+2: addr b ip ip
+    /// s+3: addi ip 1 ip
+    /// s+4: addr e a a
+    /// s+5: addi f 1 f
+    /// s+6: gtrr f c b
+    /// s+7: addr ip b ip
+    /// s+8: seti s-1 _ ip
+    /// ```
+    DivisorScan { a: usize, b: usize, c: usize, e: usize, f: usize, exit: usize },
+}
+
+fn is(instruction: &Instruction, opcode: Opcode, a: usize, b: usize, c: usize) -> bool {
+    instruction.opcode == opcode && instruction.a == a && instruction.b == b && instruction.c == c
+}
+
+/// Like `is`, but for commutative operations where the order of `a` and `b` doesn't matter.
+fn is_either(instruction: &Instruction, opcode: Opcode, a: usize, b: usize, c: usize) -> bool {
+    is(instruction, opcode, a, b, c) || is(instruction, opcode, b, a, c)
+}
+
+fn is_jump_to(instruction: &Instruction, target: usize, ip: usize) -> bool {
+    instruction.opcode == Opcode::Seti && instruction.c == ip && instruction.a.checked_add(1) == Some(target)
+}
+
+fn all_distinct(registers: &[usize]) -> bool {
+    registers.iter().enumerate().all(|(i, r)| !registers[i + 1..].contains(r))
+}
+
+impl Idiom {
+    pub fn len(&self) -> usize {
+        match self {
+            Idiom::DivisionLoop { .. } => 8,
+            Idiom::DivisorScan { .. } => 9,
+        }
+    }
+
+    fn recognise_division_loop(code: &[Instruction], s: usize, ip: usize) -> Option<Idiom> {
+        let window = code.get(s..s + 8)?;
+        let (f, t) = (window[0].a, window[0].c);
+        let (k, d) = (window[1].b, window[2].b);
+        let exit = window[5].a.checked_add(1)?;
+
+        let matches = is(&window[0], Opcode::Addi, f, 1, t)
+            && is(&window[1], Opcode::Muli, t, k, t) && k > 0
+            && is(&window[2], Opcode::Gtrr, t, d, t)
+            && is_either(&window[3], Opcode::Addr, t, ip, ip)
+            && is(&window[4], Opcode::Addi, ip, 1, ip)
+            && is_jump_to(&window[5], exit, ip)
+            && is(&window[6], Opcode::Addi, f, 1, f)
+            && is_jump_to(&window[7], s, ip)
+            && all_distinct(&[f, t, d, ip]);
+
+        if matches { Some(Idiom::DivisionLoop { f, t, d, k, exit }) } else { None }
+    }
+
+    fn recognise_divisor_scan(code: &[Instruction], s: usize, ip: usize) -> Option<Idiom> {
+        let window = code.get(s..s + 9)?;
+        let f = window[5].c;
+        let b = window[0].c;
+        let c = window[6].b;
+        let e = if window[0].a == f { window[0].b } else { window[0].a };
+        let a = window[4].c;
+
+        let matches = is_either(&window[0], Opcode::Mulr, e, f, b)
+            && is_either(&window[1], Opcode::Eqrr, b, c, b)
+            && is_either(&window[2], Opcode::Addr, b, ip, ip)
+            && is(&window[3], Opcode::Addi, ip, 1, ip)
+            && is_either(&window[4], Opcode::Addr, e, a, a)
+            && is(&window[5], Opcode::Addi, f, 1, f)
+            && is(&window[6], Opcode::Gtrr, f, c, b)
+            && is_either(&window[7], Opcode::Addr, ip, b, ip)
+            && is_jump_to(&window[8], s, ip)
+            && all_distinct(&[a, b, c, e, f, ip]);
+
+        if matches { Some(Idiom::DivisorScan { a, b, c, e, f, exit: s + 9 }) } else { None }
+    }
+
+    fn registers(&self) -> Vec<usize> {
+        match *self {
+            Idiom::DivisionLoop { f, t, d, .. } => vec![f, t, d],
+            Idiom::DivisorScan { a, b, c, e, f, .. } => vec![a, b, c, e, f],
+        }
+    }
+
+    pub fn recognise(code: &[Instruction], s: usize, ip: usize, register_count: usize) -> Option<Idiom> {
+        Idiom::recognise_division_loop(code, s, ip)
+            .or_else(|| Idiom::recognise_divisor_scan(code, s, ip))
+            .filter(|idiom| idiom.registers().iter().all(|&register| register < register_count))
+    }
+
+    /// Executes the whole loop if it finishes within `budget` steps without exceeding `max`.
+    /// Returns where execution continues and how many instructions the loop took.
+    fn apply(&self, registers: &mut Registers, budget: u64, max: usize) -> Option<(usize, u64)> {
+        let fits = |value: Option<usize>| value.filter(|&value| value <= max);
+
+        // registers which don't fit yet would be cut down the first time they're written to
+        if self.registers().iter().any(|&register| registers[register] > max) {
+            return None;
+        }
+
+        match *self {
+            Idiom::DivisionLoop { f, t, d, k, exit } => {
+                let start = registers[f];
+                let end = cmp::max(start, registers[d] / k);
+                fits(fits(end.checked_add(1))?.checked_mul(k))?;
+
+                let steps = ((end - start) as u64).checked_mul(7)?.checked_add(5)?;
+                if steps > budget {
+                    return None;
+                }
+
+                registers[f] = end;
+                registers[t] = 1;
+                Some((exit, steps))
+            }
+            Idiom::DivisorScan { a, b, c, e, f, exit } => {
+                let start = registers[f];
+                let last = cmp::max(start, registers[c]);
+                fits(last.checked_add(1))?;
+                fits(registers[e].checked_mul(last))?;
+
+                let iterations = (last - start) as u64 + 1;
+                let steps = iterations.checked_mul(8)? - 1;
+                if steps > budget {
+                    return None;
+                }
+
+                let (divisor, target) = (registers[e], registers[c]);
+                if divisor != 0 && target % divisor == 0 && (start..=last).contains(&(target / divisor)) {
+                    registers[a] = fits(registers[a].checked_add(divisor))?;
+                }
+
+                registers[f] = last + 1;
+                registers[b] = 1;
+                Some((exit, steps))
+            }
+        }
+    }
+}
+
+fn decode(address: usize, instruction: &Instruction, ip: usize, register_count: usize, arithmetic: &Arithmetic) -> Decoded {
+    let (kind_a, kind_b) = instruction.opcode.operands();
+    let value = |kind, operand| match kind {
+        Operand::Register if operand == ip => Ok(Value::Constant(address)),
+        Operand::Register if operand >= register_count => Err(FaultKind::InvalidRegister(operand)),
+        Operand::Register => Ok(Value::Register(operand)),
+        Operand::Immediate => Ok(Value::Constant(operand)),
+        Operand::Ignored => Ok(Value::Constant(0))
+    };
+
+    let opcode = instruction.opcode;
+    let (a, b) = match (value(kind_a, instruction.a), value(kind_b, instruction.b)) {
+        (Ok(a), Ok(b)) => (a, b),
+        (Err(kind), _) | (_, Err(kind)) => return Decoded::Fault(kind)
+    };
+
+    if instruction.c >= register_count {
+        return Decoded::Fault(FaultKind::InvalidRegister(instruction.c));
+    }
+
+    if instruction.c != ip {
+        return Decoded::Compute { opcode, a, b, c: instruction.c };
+    }
+
+    match (a, b) {
+        (Value::Constant(a), Value::Constant(b)) => match arithmetic.evaluate(opcode, a, b) {
+            Ok(value) => Decoded::Jump(value.wrapping_add(1)),
+            Err(kind) => Decoded::Fault(kind)
+        },
+        _ => Decoded::ComputedJump { opcode, a, b }
+    }
+}
+
+#[derive(Clone)]
+pub struct CompiledProgram {
+    ip: usize,
+    arithmetic: Arithmetic,
+    instructions: Vec<Instruction>,
+    code: Vec<Decoded>,
+    idioms: Vec<Option<Idiom>>,
+    breakpoints: Vec<bool>,
+    pub registers: Registers,
+    pub pc: usize,
+    pub steps: u64,
+    at_breakpoint: bool,
+}
+
+impl CompiledProgram {
+    pub fn new(program: &Program, registers: Registers) -> CompiledProgram {
+        CompiledProgram::with_arithmetic(program, registers, Arithmetic::default())
+    }
+
+    pub fn with_arithmetic(program: &Program, registers: Registers, arithmetic: Arithmetic) -> CompiledProgram {
+        let ip = program.ip;
+        let code = program.instructions.iter()
+            .enumerate()
+            .map(|(address, instruction)| decode(address, instruction, ip, registers.len(), &arithmetic))
+            .collect();
+        let idioms = (0..program.instructions.len())
+            .map(|s| Idiom::recognise(&program.instructions, s, ip, registers.len()))
+            .collect();
+
+        let pc = registers[ip];
+        CompiledProgram {
+            ip,
+            arithmetic,
+            instructions: program.instructions.clone(),
+            code,
+            idioms,
+            breakpoints: vec![false; program.instructions.len()],
+            registers,
+            pc,
+            steps: 0,
+            at_breakpoint: false,
+        }
+    }
+
+    /// Stops execution right before the instruction at `address` is executed.
+    /// Idioms spanning the address are disabled so the breakpoint can't be skipped.
+    pub fn set_breakpoint(&mut self, address: usize) {
+        self.breakpoints[address] = true;
+
+        for s in 0..self.idioms.len() {
+            let spans = self.idioms[s].is_some_and(|idiom| s <= address && address < s + idiom.len());
+            if spans {
+                self.idioms[s] = None;
+            }
+        }
+    }
+
+    pub fn idioms(&self) -> Vec<(usize, Idiom)> {
+        self.idioms.iter()
+            .enumerate()
+            .filter_map(|(s, idiom)| idiom.map(|idiom| (s, idiom)))
+            .collect()
+    }
+
+    /// Runs until the program halts, a breakpoint is reached or `max_steps` more instructions
+    /// have been executed. Calling it again after a breakpoint continues past it.
+    pub fn run(&mut self, max_steps: Option<u64>) -> Stop {
+        let limit = max_steps.map_or(u64::MAX, |max_steps| self.steps.saturating_add(max_steps));
+        let stop = self.execute(limit);
+
+        self.at_breakpoint = matches!(stop, Stop::Breakpoint(_));
+        self.registers[self.ip] = self.pc;
+        stop
+    }
+
+    fn execute(&mut self, limit: u64) -> Stop {
+        let mut skip_breakpoint = self.at_breakpoint;
+        let max = self.arithmetic.width.max();
+
+        while self.pc < self.code.len() {
+            let pc = self.pc;
+            if self.breakpoints[pc] && !skip_breakpoint {
+                return Stop::Breakpoint(pc);
+            }
+            skip_breakpoint = false;
+
+            if self.steps == limit {
+                return Stop::StepLimit;
+            }
+
+            if let Some(idiom) = self.idioms[pc] {
+                if let Some((exit, steps)) = idiom.apply(&mut self.registers, limit - self.steps, max) {
+                    self.pc = exit;
+                    self.steps += steps;
+                    continue;
+                }
+            }
+
+            let next = match self.code[pc] {
+                Decoded::Compute { opcode, a, b, c } => {
+                    self.arithmetic.evaluate(opcode, a.get(&self.registers), b.get(&self.registers))
+                        .map(|value| {
+                            self.registers[c] = value;
+                            pc + 1
+                        })
+                }
+                Decoded::Jump(target) => Ok(target),
+                Decoded::ComputedJump { opcode, a, b } => {
+                    self.arithmetic.evaluate(opcode, a.get(&self.registers), b.get(&self.registers))
+                        .map(|value| value.wrapping_add(1))
+                }
+                Decoded::Fault(kind) => Err(kind)
+            };
+
+            match next {
+                Ok(next) => self.pc = next,
+                Err(kind) => return Stop::Fault(self.instructions[pc].fault(pc, kind))
+            }
+            self.steps += 1;
+        }
+
+        Stop::Halted
+    }
+}
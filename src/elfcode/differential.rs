@@ -0,0 +1,278 @@
+//! Differential testing of the compiled engine against the reference interpreter.
+//!
+//! Random programs are generated with the loop idioms of the compiled engine spliced in between
+//! random instructions, so both the plain decoding and the idiom shortcuts get exercised.
+
+use super::compiler::CompiledProgram;
+use super::{interpreter, Arithmetic, Instruction, Opcode, Overflow, Program, Registers, Width, REGISTER_COUNT};
+
+const MAX_STEPS: u64 = 20_000;
+
+/// Tiny xorshift generator, we don't need anything fancier to come up with programs.
+pub struct Random(u64);
+
+impl Random {
+    pub fn new(seed: u64) -> Random {
+        Random(seed.max(1))
+    }
+
+    pub fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    pub fn below(&mut self, bound: usize) -> usize {
+        (self.next() % bound as u64) as usize
+    }
+
+    /// Picks `count` distinct registers, none of which is `ip`.
+    fn registers(&mut self, count: usize, ip: usize) -> Vec<usize> {
+        let mut registers: Vec<usize> = (0..REGISTER_COUNT).filter(|&r| r != ip).collect();
+        for i in 0..registers.len() {
+            let j = i + self.below(registers.len() - i);
+            registers.swap(i, j);
+        }
+
+        registers.truncate(count);
+        registers
+    }
+}
+
+/// Mostly valid registers, but every now and then one that doesn't exist.
+fn random_register(random: &mut Random) -> usize {
+    if random.below(40) == 0 { REGISTER_COUNT } else { random.below(REGISTER_COUNT) }
+}
+
+fn random_instruction(random: &mut Random) -> Instruction {
+    let opcode = Opcode::ALL[random.below(Opcode::ALL.len())];
+    let (kind_a, kind_b) = opcode.operands();
+    let mut operand = |kind| match kind {
+        super::Operand::Register => random_register(random),
+        _ => random.below(24)
+    };
+
+    let a = operand(kind_a);
+    let b = operand(kind_b);
+    Instruction::new(opcode, a, b, random_register(random))
+}
+
+fn random_arithmetic(random: &mut Random) -> Arithmetic {
+    let width = [Width::W8, Width::W16, Width::W32, Width::W64][random.below(4)];
+    let overflow = [Overflow::Wrap, Overflow::Saturate, Overflow::Trap][random.below(3)];
+    Arithmetic::new(width, overflow)
+}
+
+fn division_loop(random: &mut Random, s: usize, ip: usize) -> Vec<Instruction> {
+    let registers = random.registers(3, ip);
+    let (f, t, d) = (registers[0], registers[1], registers[2]);
+    let k = 1 + random.below(40);
+
+    vec![
+        Instruction::new(Opcode::Seti, random.below(8), 0, f),
+        Instruction::new(Opcode::Addi, f, 1, t),
+        Instruction::new(Opcode::Muli, t, k, t),
+        Instruction::new(Opcode::Gtrr, t, d, t),
+        Instruction::new(Opcode::Addr, t, ip, ip),
+        Instruction::new(Opcode::Addi, ip, 1, ip),
+        Instruction::new(Opcode::Seti, s + 9 - 1, 0, ip),
+        Instruction::new(Opcode::Addi, f, 1, f),
+        Instruction::new(Opcode::Seti, s, 0, ip),
+    ]
+}
+
+fn divisor_scan(random: &mut Random, s: usize, ip: usize) -> Vec<Instruction> {
+    let registers = random.registers(5, ip);
+    let (a, b, c, e, f) = (registers[0], registers[1], registers[2], registers[3], registers[4]);
+
+    vec![
+        Instruction::new(Opcode::Seti, random.below(4), 0, f),
+        Instruction::new(Opcode::Mulr, e, f, b),
+        Instruction::new(Opcode::Eqrr, b, c, b),
+        Instruction::new(Opcode::Addr, b, ip, ip),
+        Instruction::new(Opcode::Addi, ip, 1, ip),
+        Instruction::new(Opcode::Addr, e, a, a),
+        Instruction::new(Opcode::Addi, f, 1, f),
+        Instruction::new(Opcode::Gtrr, f, c, b),
+        Instruction::new(Opcode::Addr, ip, b, ip),
+        Instruction::new(Opcode::Seti, s, 0, ip),
+    ]
+}
+
+pub fn random_program(random: &mut Random) -> Program {
+    let ip = random.below(REGISTER_COUNT);
+    let mut instructions = Vec::new();
+
+    for _ in 0..1 + random.below(12) {
+        let s = instructions.len();
+        match random.below(6) {
+            0 => instructions.extend(division_loop(random, s, ip)),
+            1 => instructions.extend(divisor_scan(random, s, ip)),
+            _ => instructions.push(random_instruction(random))
+        }
+    }
+
+    Program { ip, instructions }
+}
+
+/// Runs `count` random programs on both engines and describes the first disagreement, if any.
+pub fn run(count: usize, seed: u64) -> Result<String, String> {
+    let mut random = Random::new(seed);
+    let mut idioms_used = 0;
+
+    for case in 0..count {
+        let program = random_program(&mut random);
+        let arithmetic = random_arithmetic(&mut random);
+        let registers: Registers = (0..REGISTER_COUNT).map(|_| random.below(300)).collect();
+        let mut registers = registers;
+        registers[program.ip] = 0;
+
+        let mut expected = registers.clone();
+        let (expected_stop, expected_steps) = interpreter::run(&program, &mut expected, Some(MAX_STEPS), &arithmetic);
+
+        let mut compiled = CompiledProgram::with_arithmetic(&program, registers.clone(), arithmetic);
+        idioms_used += compiled.idioms().len();
+        let stop = compiled.run(Some(MAX_STEPS));
+
+        if (stop, compiled.steps, &compiled.registers) != (expected_stop, expected_steps, &expected) {
+            return Err(format!(
+                "case {} disagrees ({:?})\n{}\nstart:       {:?}\ninterpreter: {:?} after {} steps, {:?}\ncompiled:    {:?} after {} steps, {:?}",
+                case, arithmetic, program, registers, expected, expected_steps, expected_stop,
+                compiled.registers, compiled.steps, stop
+            ));
+        }
+    }
+
+    Ok(format!("{} programs ({} idioms) behaved identically", count, idioms_used))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use elfcode::Stop;
+
+    const DAY19: &str = "#ip 3
+addi 3 16 3
+seti 1 0 4
+seti 1 5 1
+mulr 4 1 5
+eqrr 5 2 5
+addr 5 3 3
+addi 3 1 3
+addr 4 0 0
+addi 1 1 1
+gtrr 1 2 5
+addr 3 5 3
+seti 2 5 3
+addi 4 1 4
+gtrr 4 2 5
+addr 5 3 3
+seti 1 2 3
+mulr 3 3 3
+addi 2 2 2
+mulr 2 2 2
+mulr 3 2 2
+muli 2 11 2
+addi 5 6 5
+mulr 5 3 5
+addi 5 9 5
+addr 2 5 2
+addr 3 0 3
+seti 0 8 3
+setr 3 2 5
+mulr 5 3 5
+addr 3 5 5
+mulr 3 5 5
+muli 5 14 5
+mulr 5 3 5
+addr 2 5 2
+seti 0 0 0
+seti 0 0 3";
+
+    const DAY21: &str = "#ip 4
+seti 123 0 3
+bani 3 456 3
+eqri 3 72 3
+addr 3 4 4
+seti 0 0 4
+seti 0 2 3
+bori 3 65536 2
+seti 4843319 1 3
+bani 2 255 5
+addr 3 5 3
+bani 3 16777215 3
+muli 3 65899 3
+bani 3 16777215 3
+gtir 256 2 5
+addr 5 4 4
+addi 4 1 4
+seti 27 4 4
+seti 0 7 5
+addi 5 1 1
+muli 1 256 1
+gtrr 1 2 1
+addr 1 4 4
+addi 4 1 4
+seti 25 1 4
+addi 5 1 5
+seti 17 8 4
+setr 5 2 2
+seti 7 4 4
+eqrr 3 0 5
+addr 5 4 4
+seti 5 6 4";
+
+    fn parse(source: &str) -> Program {
+        let lines: Vec<String> = source.lines().map(String::from).collect();
+        Program::parse(&lines).unwrap()
+    }
+
+    /// Runs `program` on both engines and checks they stop the same way with the same registers.
+    fn assert_same(program: &Program, registers: Registers, max_steps: u64) -> (Stop, Registers) {
+        let arithmetic = Arithmetic::default();
+        let mut expected = registers.clone();
+        let (expected_stop, expected_steps) = interpreter::run(program, &mut expected, Some(max_steps), &arithmetic);
+
+        let mut compiled = CompiledProgram::new(program, registers);
+        assert!(!compiled.idioms().is_empty(), "no idiom was recognised");
+        let stop = compiled.run(Some(max_steps));
+
+        assert_eq!((stop, compiled.steps, &compiled.registers), (expected_stop, expected_steps, &expected));
+        (stop, expected)
+    }
+
+    #[test]
+    fn random_programs_agree() {
+        for &seed in &[1, 2018, 0xdead_beef] {
+            if let Err(e) = run(3_000, seed) {
+                panic!("seed {}: {}", seed, e);
+            }
+        }
+    }
+
+    #[test]
+    fn day19_divisor_scan_agrees() {
+        let (stop, registers) = assert_same(&parse(DAY19), vec![0; REGISTER_COUNT], 10_000_000);
+        assert_eq!(stop, Stop::Halted);
+        assert_eq!(registers[0], 978);
+    }
+
+    #[test]
+    fn day21_division_loop_agrees() {
+        let program = parse(DAY21);
+
+        // the value register 0 is compared against first is the one that halts soonest
+        let mut machine = CompiledProgram::new(&program, vec![0; REGISTER_COUNT]);
+        machine.set_breakpoint(28);
+        assert_eq!(machine.run(None), Stop::Breakpoint(28));
+        let mut registers = vec![0; REGISTER_COUNT];
+        registers[0] = machine.registers[3];
+
+        let (stop, _) = assert_same(&program, registers, 1_000_000);
+        assert_eq!(stop, Stop::Halted);
+
+        // without a halting value it loops forever, compare a long stretch of that instead
+        assert_same(&program, vec![0; REGISTER_COUNT], 1_000_000);
+    }
+}
@@ -0,0 +1,143 @@
+//! Which values of register 0 make a program halt, and how long it takes.
+//!
+//! Programs like the one from day 21 only look at register 0 once, to compare it with a value
+//! they computed. Every time that comparison is reached we try what would happen if register 0
+//! held the compared value and keep going as if it didn't, until the program starts repeating itself.
+
+use std::collections::HashSet;
+
+use super::compiler::CompiledProgram;
+use super::{Opcode, Operand, Program, Registers, Stop, REGISTER_COUNT};
+
+/// How many instructions a fork runs between checks whether it's stuck in a loop.
+const FORK_CHUNK_STEPS: u64 = 1_000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Halt {
+    pub value: usize,
+    /// Exact number of executed instructions if register 0 starts out with `value`.
+    pub steps: u64,
+}
+
+fn reads_register_zero(program: &Program, address: usize) -> bool {
+    let instruction = &program.instructions[address];
+    let (kind_a, kind_b) = instruction.opcode.operands();
+
+    (kind_a == Operand::Register && instruction.a == 0) || (kind_b == Operand::Register && instruction.b == 0)
+}
+
+/// The value register 0 is compared against by the instruction at `address`.
+fn compared_value(program: &Program, address: usize, registers: &Registers) -> Result<usize, String> {
+    let instruction = &program.instructions[address];
+
+    match instruction.opcode {
+        Opcode::Eqrr if instruction.a == 0 => Ok(registers[instruction.b]),
+        Opcode::Eqrr => Ok(registers[instruction.a]),
+        Opcode::Eqri => Ok(instruction.b),
+        Opcode::Eqir => Ok(instruction.a),
+        _ => Err(format!("instruction {} (`{}`) uses register 0 for something other than a comparison", address, instruction))
+    }
+}
+
+/// Lists every value of register 0 that makes the program halt, in the order they're checked.
+/// Only the first time a value comes up matters, so each value is listed once.
+///
+/// With `max_steps` only values that halt the program in fewer instructions are listed and the
+/// search stops once it gets that far. Values for which the program never halts are left out.
+pub fn halting_values(program: &Program, max_steps: Option<u64>) -> Result<Vec<Halt>, String> {
+    if program.ip == 0 {
+        return Err("register 0 is the instruction pointer".to_string());
+    }
+
+    let checks: Vec<usize> = (0..program.instructions.len())
+        .filter(|&address| reads_register_zero(program, address))
+        .collect();
+    if checks.is_empty() {
+        return Err("the program never looks at register 0".to_string());
+    }
+
+    // register 0 only holds a value none of the comparisons produce as long as we're just looking
+    let mut registers = vec![0; REGISTER_COUNT];
+    registers[0] = usize::MAX;
+
+    let mut machine = CompiledProgram::new(program, registers);
+    for &address in checks.iter() {
+        machine.set_breakpoint(address);
+    }
+
+    let limit = max_steps.unwrap_or(u64::MAX);
+    let mut halts = Vec::new();
+    let mut seen_values = HashSet::new();
+    let mut seen_states = HashSet::new();
+
+    loop {
+        let address = match machine.run(Some(limit - machine.steps)) {
+            Stop::Breakpoint(address) => address,
+            Stop::Fault(fault) => return Err(fault.to_string()),
+            _ => break
+        };
+
+        // once the machine is somewhere it's been before nothing new is going to happen
+        if !seen_states.insert((address, machine.registers.clone())) {
+            break;
+        }
+
+        let value = compared_value(program, address, &machine.registers)?;
+        if !seen_values.insert(value) {
+            continue;
+        }
+
+        let mut registers = machine.registers.clone();
+        registers[0] = value;
+        let mut fork = CompiledProgram::new(program, registers);
+        fork.steps = machine.steps;
+        if halts_within(&mut fork, limit) {
+            halts.push(Halt { value, steps: fork.steps });
+        }
+    }
+
+    Ok(halts)
+}
+
+/// Runs `fork` until it halts, faults, reaches `limit` or turns out to be stuck in a loop.
+///
+/// The fork runs in chunks of the same length, so its state after each chunk only depends on the
+/// state after the one before. Brent's cycle detection on those states finds every endless loop.
+fn halts_within(fork: &mut CompiledProgram, limit: u64) -> bool {
+    let mut saved_registers = None;
+    let mut power = 1;
+    let mut since_saved = 0;
+
+    loop {
+        match fork.run(Some((limit - fork.steps).min(FORK_CHUNK_STEPS))) {
+            Stop::Halted => return fork.steps < limit,
+            Stop::StepLimit if fork.steps < limit => {}
+            _ => return false
+        }
+
+        if saved_registers.as_ref() == Some(&fork.registers) {
+            return false;
+        }
+
+        since_saved += 1;
+        if since_saved == power {
+            saved_registers = Some(fork.registers.clone());
+            power *= 2;
+            since_saved = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn endless_forks_are_left_out() {
+        let source = "#ip 5\nseti 0 0 3\naddi 1 1 1\nbani 1 7 1\neqrr 1 0 2\naddr 2 5 5\nseti 0 0 5\nseti 5 0 5";
+        let lines: Vec<String> = source.lines().map(String::from).collect();
+        let program = Program::parse(&lines).unwrap();
+
+        assert_eq!(halting_values(&program, None), Ok(Vec::new()));
+    }
+}
@@ -0,0 +1,25 @@
+//! The straightforward interpreter. It's slow, but it's the reference every other engine is checked against.
+
+use super::{Arithmetic, Program, Registers, Stop};
+
+/// Runs the program until it halts or `max_steps` instructions have been executed.
+/// Returns the reason for stopping and the number of executed instructions.
+pub fn run(program: &Program, registers: &mut Registers, max_steps: Option<u64>, arithmetic: &Arithmetic) -> (Stop, u64) {
+    let ip = program.ip;
+    let mut steps = 0;
+
+    while let Some(instruction) = program.instructions.get(registers[ip]) {
+        if Some(steps) == max_steps {
+            return (Stop::StepLimit, steps);
+        }
+
+        if let Err(kind) = instruction.execute(registers, arithmetic) {
+            return (Stop::Fault(instruction.fault(registers[ip], kind)), steps);
+        }
+
+        registers[ip] = registers[ip].wrapping_add(1);
+        steps += 1;
+    }
+
+    (Stop::Halted, steps)
+}
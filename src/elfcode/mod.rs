@@ -0,0 +1,345 @@
+//! Shared representation of the wrist device's "ElfCode" used by days 16, 19 and 21.
+
+use std::fmt;
+
+pub mod assembler;
+pub mod cfg;
+pub mod compiler;
+pub mod differential;
+pub mod halting;
+pub mod interpreter;
+pub mod profiler;
+
+pub const REGISTER_COUNT: usize = 6;
+
+pub type Registers = Vec<usize>;
+
+/// Why a machine stopped executing.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stop {
+    Halted,
+    StepLimit,
+    Breakpoint(usize),
+    Fault(Fault),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Width {
+    W8,
+    W16,
+    W32,
+    W64,
+}
+
+impl Width {
+    pub fn parse(text: &str) -> Result<Width, String> {
+        match text {
+            "8" => Ok(Width::W8),
+            "16" => Ok(Width::W16),
+            "32" => Ok(Width::W32),
+            "64" => Ok(Width::W64),
+            _ => Err(format!("Unknown register width `{}`, expected 8, 16, 32 or 64", text))
+        }
+    }
+
+    pub fn bits(self) -> u32 {
+        match self {
+            Width::W8 => 8,
+            Width::W16 => 16,
+            Width::W32 => 32,
+            Width::W64 => 64,
+        }
+    }
+
+    /// Largest value a register can hold.
+    pub fn max(self) -> usize {
+        (u64::MAX >> (64 - self.bits())) as usize
+    }
+}
+
+/// What happens when the result of an instruction doesn't fit into a register.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Overflow {
+    Wrap,
+    Saturate,
+    Trap,
+}
+
+impl Overflow {
+    pub fn parse(text: &str) -> Result<Overflow, String> {
+        match text {
+            "wrap" => Ok(Overflow::Wrap),
+            "saturate" => Ok(Overflow::Saturate),
+            "trap" => Ok(Overflow::Trap),
+            _ => Err(format!("Unknown overflow policy `{}`, expected wrap, saturate or trap", text))
+        }
+    }
+}
+
+/// Register width and overflow policy a machine computes with.
+/// The default of 64-bit registers that wrap around is what the puzzles assume.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Arithmetic {
+    pub width: Width,
+    pub overflow: Overflow,
+}
+
+impl Default for Arithmetic {
+    fn default() -> Arithmetic {
+        Arithmetic { width: Width::W64, overflow: Overflow::Wrap }
+    }
+}
+
+impl Arithmetic {
+    pub fn new(width: Width, overflow: Overflow) -> Arithmetic {
+        Arithmetic { width, overflow }
+    }
+
+    pub fn evaluate(&self, opcode: Opcode, a: usize, b: usize) -> Result<usize, FaultKind> {
+        let exact = match opcode {
+            Opcode::Addr | Opcode::Addi => a as u128 + b as u128,
+            Opcode::Mulr | Opcode::Muli => a as u128 * b as u128,
+            _ => opcode.evaluate(a, b) as u128
+        };
+
+        let max = self.width.max();
+        if exact <= max as u128 {
+            return Ok(exact as usize);
+        }
+
+        match self.overflow {
+            Overflow::Wrap => Ok((exact & max as u128) as usize),
+            Overflow::Saturate => Ok(max),
+            Overflow::Trap => Err(FaultKind::Overflow(self.width))
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FaultKind {
+    InvalidRegister(usize),
+    Overflow(Width),
+}
+
+/// An instruction which couldn't be executed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Fault {
+    pub address: usize,
+    pub instruction: Instruction,
+    pub kind: FaultKind,
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "instruction {} (`{}`) ", self.address, self.instruction)?;
+        match self.kind {
+            FaultKind::InvalidRegister(register) => write!(f, "uses register {} which doesn't exist", register),
+            FaultKind::Overflow(width) => write!(f, "overflows a {}-bit register", width.bits())
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operand {
+    Register,
+    Immediate,
+    Ignored,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Opcode {
+    Addr,
+    Addi,
+    Mulr,
+    Muli,
+    Banr,
+    Bani,
+    Borr,
+    Bori,
+    Setr,
+    Seti,
+    Gtir,
+    Gtri,
+    Gtrr,
+    Eqir,
+    Eqri,
+    Eqrr,
+}
+
+impl Opcode {
+    pub const ALL: [Opcode; 16] = [
+        Opcode::Addr, Opcode::Addi, Opcode::Mulr, Opcode::Muli,
+        Opcode::Banr, Opcode::Bani, Opcode::Borr, Opcode::Bori,
+        Opcode::Setr, Opcode::Seti, Opcode::Gtir, Opcode::Gtri,
+        Opcode::Gtrr, Opcode::Eqir, Opcode::Eqri, Opcode::Eqrr,
+    ];
+
+    pub fn from_mnemonic(mnemonic: &str) -> Option<Opcode> {
+        Opcode::ALL.iter().find(|op| op.mnemonic() == mnemonic).cloned()
+    }
+
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            Opcode::Addr => "addr",
+            Opcode::Addi => "addi",
+            Opcode::Mulr => "mulr",
+            Opcode::Muli => "muli",
+            Opcode::Banr => "banr",
+            Opcode::Bani => "bani",
+            Opcode::Borr => "borr",
+            Opcode::Bori => "bori",
+            Opcode::Setr => "setr",
+            Opcode::Seti => "seti",
+            Opcode::Gtir => "gtir",
+            Opcode::Gtri => "gtri",
+            Opcode::Gtrr => "gtrr",
+            Opcode::Eqir => "eqir",
+            Opcode::Eqri => "eqri",
+            Opcode::Eqrr => "eqrr",
+        }
+    }
+
+    /// How the A and B operands are interpreted. C is always a register.
+    pub fn operands(self) -> (Operand, Operand) {
+        use self::Operand::*;
+
+        match self {
+            Opcode::Addr | Opcode::Mulr | Opcode::Banr | Opcode::Borr | Opcode::Gtrr | Opcode::Eqrr => (Register, Register),
+            Opcode::Addi | Opcode::Muli | Opcode::Bani | Opcode::Bori | Opcode::Gtri | Opcode::Eqri => (Register, Immediate),
+            Opcode::Gtir | Opcode::Eqir => (Immediate, Register),
+            Opcode::Setr => (Register, Ignored),
+            Opcode::Seti => (Immediate, Ignored),
+        }
+    }
+
+    /// Applies the operation to already resolved operand values.
+    pub fn evaluate(self, a: usize, b: usize) -> usize {
+        match self {
+            Opcode::Addr | Opcode::Addi => a.wrapping_add(b),
+            Opcode::Mulr | Opcode::Muli => a.wrapping_mul(b),
+            Opcode::Banr | Opcode::Bani => a & b,
+            Opcode::Borr | Opcode::Bori => a | b,
+            Opcode::Setr | Opcode::Seti => a,
+            Opcode::Gtir | Opcode::Gtri | Opcode::Gtrr => (a > b) as usize,
+            Opcode::Eqir | Opcode::Eqri | Opcode::Eqrr => (a == b) as usize,
+        }
+    }
+
+    /// Symbol used by the decompiled view, e.g. `+` for `addr` and `addi`.
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Opcode::Addr | Opcode::Addi => "+",
+            Opcode::Mulr | Opcode::Muli => "*",
+            Opcode::Banr | Opcode::Bani => "&",
+            Opcode::Borr | Opcode::Bori => "|",
+            Opcode::Setr | Opcode::Seti => "",
+            Opcode::Gtir | Opcode::Gtri | Opcode::Gtrr => ">",
+            Opcode::Eqir | Opcode::Eqri | Opcode::Eqrr => "==",
+        }
+    }
+}
+
+impl fmt::Display for Opcode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.mnemonic())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Instruction {
+    pub opcode: Opcode,
+    pub a: usize,
+    pub b: usize,
+    pub c: usize,
+}
+
+impl Instruction {
+    pub fn new(opcode: Opcode, a: usize, b: usize, c: usize) -> Instruction {
+        Instruction { opcode, a, b, c }
+    }
+
+    pub fn parse(line: &str) -> Result<Instruction, String> {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        if parts.len() != 4 {
+            return Err(format!("expected `op a b c`, got `{}`", line));
+        }
+
+        let opcode = Opcode::from_mnemonic(parts[0])
+            .ok_or_else(|| format!("unknown opcode `{}`", parts[0]))?;
+
+        let mut operands = [0; 3];
+        for (operand, raw) in operands.iter_mut().zip(&parts[1..]) {
+            *operand = raw.parse().map_err(|_| format!("invalid operand `{}`", raw))?;
+        }
+
+        Ok(Instruction::new(opcode, operands[0], operands[1], operands[2]))
+    }
+
+    pub fn execute(&self, registers: &mut Registers, arithmetic: &Arithmetic) -> Result<(), FaultKind> {
+        let (kind_a, kind_b) = self.opcode.operands();
+        let value = |kind, operand: usize| match kind {
+            Operand::Register => registers.get(operand).cloned().ok_or(FaultKind::InvalidRegister(operand)),
+            Operand::Immediate => Ok(operand),
+            Operand::Ignored => Ok(0)
+        };
+
+        let (a, b) = (value(kind_a, self.a)?, value(kind_b, self.b)?);
+        if self.c >= registers.len() {
+            return Err(FaultKind::InvalidRegister(self.c));
+        }
+
+        registers[self.c] = arithmetic.evaluate(self.opcode, a, b)?;
+        Ok(())
+    }
+
+    pub fn fault(&self, address: usize, kind: FaultKind) -> Fault {
+        Fault { address, instruction: *self, kind }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} {} {}", self.opcode, self.a, self.b, self.c)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Program {
+    pub ip: usize,
+    pub instructions: Vec<Instruction>,
+}
+
+impl Program {
+    /// Parses the `#ip n` header followed by one numeric instruction per line.
+    pub fn parse(lines: &[String]) -> Result<Program, String> {
+        let (header, body) = lines.split_first().ok_or("empty program")?;
+
+        let ip = match header.split_whitespace().collect::<Vec<&str>>()[..] {
+            ["#ip", raw] => match raw.parse() {
+                Ok(ip) if ip < REGISTER_COUNT => ip,
+                _ => return Err(format!("invalid ip register `{}`", raw))
+            },
+            _ => return Err(format!("expected `#ip n`, got `{}`", header))
+        };
+
+        let mut instructions = Vec::new();
+        for (index, line) in body.iter().enumerate() {
+            let instruction = Instruction::parse(line)
+                .map_err(|e| format!("line {}: {}", index + 2, e))?;
+            instructions.push(instruction);
+        }
+
+        Ok(Program { ip, instructions })
+    }
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#ip {}", self.ip)?;
+        for instruction in self.instructions.iter() {
+            write!(f, "\n{}", instruction)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,183 @@
+//! Profiling run mode: executes a program on the reference semantics and records where the time goes.
+
+use std::collections::BTreeMap;
+
+use super::assembler::decompile_instruction;
+use super::{Arithmetic, Program, Registers, Stop};
+
+/// A loop found through a backwards jump from `end` to `start`.
+#[derive(Debug, PartialEq)]
+pub struct Loop {
+    pub start: usize,
+    pub end: usize,
+    pub iterations: u64,
+    /// Instructions executed inside `start..=end`, a rough measure of how hot the loop is.
+    pub executed: u64,
+}
+
+pub struct Profile {
+    pub stop: Stop,
+    pub steps: u64,
+    pub counts: Vec<u64>,
+    /// Number of times control went from one address to another one that isn't the next instruction.
+    pub edges: BTreeMap<(usize, usize), u64>,
+    /// Smallest and largest value each register held.
+    pub ranges: Vec<(usize, usize)>,
+}
+
+pub fn profile(program: &Program, mut registers: Registers, max_steps: Option<u64>) -> Profile {
+    let ip = program.ip;
+    let mut counts = vec![0; program.instructions.len()];
+    let mut edges = BTreeMap::new();
+    let mut ranges: Vec<(usize, usize)> = registers.iter().map(|&value| (value, value)).collect();
+    let mut steps = 0;
+    let mut stop = Stop::Halted;
+    let arithmetic = Arithmetic::default();
+
+    while let Some(instruction) = program.instructions.get(registers[ip]) {
+        if Some(steps) == max_steps {
+            stop = Stop::StepLimit;
+            break;
+        }
+
+        let pc = registers[ip];
+        if let Err(kind) = instruction.execute(&mut registers, &arithmetic) {
+            stop = Stop::Fault(instruction.fault(pc, kind));
+            break;
+        }
+
+        registers[ip] = registers[ip].wrapping_add(1);
+        steps += 1;
+        counts[pc] += 1;
+
+        if registers[ip] != pc + 1 {
+            *edges.entry((pc, registers[ip])).or_insert(0) += 1;
+        }
+
+        for (range, &value) in ranges.iter_mut().zip(registers.iter()) {
+            if value < range.0 {
+                range.0 = value;
+            } else if value > range.1 {
+                range.1 = value;
+            }
+        }
+    }
+
+    Profile { stop, steps, counts, edges, ranges }
+}
+
+impl Profile {
+    /// Loops sorted from hottest to coldest.
+    pub fn loops(&self) -> Vec<Loop> {
+        let mut loops: Vec<Loop> = self.edges.iter()
+            .filter(|&(&(from, to), _)| to <= from)
+            .map(|(&(end, start), &iterations)| Loop {
+                start,
+                end,
+                iterations,
+                executed: self.counts[start..=end].iter().sum(),
+            })
+            .collect();
+
+        loops.sort_by(|a, b| b.executed.cmp(&a.executed).then(a.start.cmp(&b.start)));
+        loops
+    }
+
+    fn stop_name(&self) -> &'static str {
+        match self.stop {
+            Stop::Halted => "halted",
+            Stop::StepLimit => "step limit",
+            Stop::Breakpoint(_) => "breakpoint",
+            Stop::Fault(_) => "fault"
+        }
+    }
+
+    /// The program with the execution count next to each line, followed by the jumps, loops and registers.
+    pub fn listing(&self, program: &Program) -> String {
+        let mut lines = vec![format!("{} instructions executed ({})", self.steps, self.stop_name())];
+        if let Stop::Fault(fault) = self.stop {
+            lines.push(fault.to_string());
+        }
+        lines.push(String::new());
+
+        for (index, instruction) in program.instructions.iter().enumerate() {
+            let percentage = 100.0 * self.counts[index] as f64 / self.steps.max(1) as f64;
+            lines.push(format!(
+                "{:>12} {:>6.2}% | {:>2}: {:<16} ; {}",
+                self.counts[index], percentage, index, instruction.to_string(),
+                decompile_instruction(index, instruction, program.ip)
+            ));
+        }
+
+        lines.push(String::new());
+        lines.push("jumps:".to_string());
+        for (&(from, to), &count) in self.edges.iter() {
+            lines.push(format!("{:>12} | {:>2} -> {}", count, from, to));
+        }
+
+        lines.push(String::new());
+        lines.push("loops:".to_string());
+        for hot_loop in self.loops() {
+            lines.push(format!(
+                "{:>12} | {:>2} - {:<2} {} iterations",
+                hot_loop.executed, hot_loop.start, hot_loop.end, hot_loop.iterations
+            ));
+        }
+
+        lines.push(String::new());
+        lines.push("registers:".to_string());
+        for (register, &(min, max)) in self.ranges.iter().enumerate() {
+            lines.push(format!("{:>12} | {} ..= {}", register, min, max));
+        }
+
+        lines.join("\n")
+    }
+
+    pub fn to_json(&self, program: &Program) -> String {
+        let instructions: Vec<String> = program.instructions.iter()
+            .enumerate()
+            .map(|(index, instruction)| format!(
+                "{{\"index\":{},\"instruction\":\"{}\",\"count\":{}}}",
+                index, instruction, self.counts[index]
+            ))
+            .collect();
+        let edges: Vec<String> = self.edges.iter()
+            .map(|(&(from, to), &count)| format!("{{\"from\":{},\"to\":{},\"count\":{}}}", from, to, count))
+            .collect();
+        let loops: Vec<String> = self.loops().iter()
+            .map(|l| format!(
+                "{{\"start\":{},\"end\":{},\"iterations\":{},\"executed\":{}}}",
+                l.start, l.end, l.iterations, l.executed
+            ))
+            .collect();
+        let registers: Vec<String> = self.ranges.iter()
+            .enumerate()
+            .map(|(register, &(min, max))| format!("{{\"register\":{},\"min\":{},\"max\":{}}}", register, min, max))
+            .collect();
+
+        format!(
+            "{{\"steps\":{},\"stop\":\"{}\",\"instructions\":[{}],\"edges\":[{}],\"loops\":[{}],\"registers\":[{}]}}",
+            self.steps, self.stop_name(), instructions.join(","), edges.join(","), loops.join(","), registers.join(",")
+        )
+    }
+}
+
+/// Entry point for the `profile` mode: `[listing|json] [max steps] [initial value of register 0]`.
+pub fn run(program: &Program, args: &[&str]) -> Result<String, String> {
+    let max_steps = match args.get(1) {
+        Some(steps) => Some(steps.parse().map_err(|_| format!("invalid step limit `{}`", steps))?),
+        None => None
+    };
+
+    let mut registers = vec![0; super::REGISTER_COUNT];
+    if let Some(value) = args.get(2) {
+        registers[0] = value.parse().map_err(|_| format!("invalid register value `{}`", value))?;
+    }
+
+    let profile = profile(program, registers, max_steps);
+    match args.first() {
+        None | Some(&"listing") => Ok(profile.listing(program)),
+        Some(&"json") => Ok(profile.to_json(program)),
+        Some(format) => Err(format!("Unknown format `{}`, expected listing or json", format))
+    }
+}
@@ -11,6 +11,8 @@ use std::io::{BufRead, BufReader, stdin};
 
 use clap::App;
 
+mod automaton;
+mod elfcode;
 mod puzzles;
 mod utils;
 
@@ -31,6 +33,20 @@ fn main() {
         reader = Box::new(stdin.lock());
     }
 
+    if let Some(mode) = matches.value_of("mode") {
+        let args: Vec<&str> = matches.values_of("args").map(|args| args.collect()).unwrap_or_default();
+        let out = puzzles::run_mode(day, mode, &args, reader);
+
+        match out {
+            Ok(out) => println!("{}", out),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
     println!("Solving day {}/{}", day, part);
 
     let out = puzzles::solve(day, part, reader);
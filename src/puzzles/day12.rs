@@ -1,117 +1,79 @@
-use std::collections::HashMap;
-use std::collections::VecDeque;
-use std::i32;
-use std::i64;
 use std::io::BufRead;
 
+use automaton::{Alphabet, Automaton, Boundary, Grid, History, Neighbourhood};
+use automaton::rules::PatternRules;
 use utils;
 
-fn get_input<T: BufRead>(input: T) -> (Vec<char>, HashMap<String, char>) {
-    let lines = utils::get_lines(input);
+/// Generations to simulate before giving up on the pots ever repeating.
+const MAX_GENERATIONS: u64 = 10_000;
 
-    let split = lines.first().expect("No initial state found")
-        .split(": ").take(2).collect::<Vec<&str>>();
-    let initial_state = split.last().expect("Initial State invalid");
+fn get_input<T: BufRead>(input: T) -> Result<History<PatternRules>, String> {
+    let alphabet = Alphabet::new(".#");
+    let lines = utils::get_lines_until_exit(input);
+    let lines: Vec<&str> = lines.iter().map(|line| line.trim()).filter(|line| !line.is_empty()).collect();
 
-    let mut rules = HashMap::new();
+    let (header, rules) = lines.split_first().ok_or("No initial state found")?;
+    let initial_state = match header.split(": ").collect::<Vec<&str>>()[..] {
+        ["initial state", initial_state] => initial_state,
+        _ => return Err(format!("expected `initial state: ...`, got `{}`", header))
+    };
 
-    for rule in lines[1..].iter() {
-        if let [state, result] = rule.split(" => ").collect::<Vec<&str>>()[..] {
-            rules.insert(state.to_string(), result.chars().next().unwrap());
-        }
-    }
+    let pots = Grid::parse(&[initial_state], &alphabet)?;
+    // inputs may leave out the rules that result in an empty pot
+    let rules = PatternRules::parse(rules, &alphabet, Some(0))?;
+    let automaton = Automaton::new(pots, Neighbourhood::line(2), rules, Boundary::Infinite(0))?;
 
-
-    (initial_state.chars().collect(), rules)
+    Ok(History::new(automaton))
 }
 
-fn run_step(state: &mut Vec<char>, rules: &HashMap<String, char>, index_offset: &mut i64) {
-    let mut pattern: VecDeque<char> = VecDeque::from(vec!['.', '.', '.', '.', '.']);
-
-    let mut index = -2 + *index_offset;
-    let mut next_state: Vec<char> = Vec::new();
-
-    for c in state.iter().chain(vec!['.', '.', '.', '.', '.'].iter()) {
-        pattern.pop_front();
-        pattern.push_back(*c);
-
-        let result = *rules.get(pattern.iter().collect::<String>().as_str())
-            .expect(format!("Rules didn't include pattern {:?}", pattern.iter().collect::<String>().as_str()).as_str());
-
-        next_state.push(result);
-        if index < *index_offset {
-            *index_offset = index;
-        }
-
-        index += 1;
-    }
-
-    while next_state.starts_with(&['.']) {
-        next_state.remove(0);
-        *index_offset += 1;
-    }
-
-    while next_state.ends_with(&['.']) {
-        next_state.pop();
-    }
-
-    *state = next_state;
+/// Sum of the numbers of all pots with a plant in them after the given number of generations.
+fn pot_sum(history: &mut History<PatternRules>, generations: u64) -> Result<i128, String> {
+    let pots = history.at(generations, MAX_GENERATIONS)?;
+    Ok(pots.positions(1).map(|(x, _)| x).sum())
 }
 
-fn calculate_sum(state: &Vec<char>, index_offset: i64) -> i64 {
-    let mut sum = 0;
-
-    for (i, &c) in state.iter().enumerate() {
-        if c == '#' {
-            sum += i as i64 + index_offset;
-        }
-    }
-
-    sum
+pub fn solve_first<T: BufRead>(input: T) -> Result<i128, String> {
+    pot_sum(&mut get_input(input)?, 20)
 }
 
+pub fn solve_second<T: BufRead>(input: T) -> Result<i128, String> {
+    pot_sum(&mut get_input(input)?, 50_000_000_000)
+}
 
-pub fn solve_first<T: BufRead>(input: T) -> i32 {
-    let (mut state, rules) = get_input(input);
-    let mut index_offset = 0;
-
-    for _ in 0..20 {
-        run_step(&mut state, &rules, &mut index_offset);
+pub fn solve<T: BufRead>(problem: u8, input: T) -> Result<String, String> {
+    match problem {
+        1 => solve_first(input).map(|result| result.to_string()),
+        2 => solve_second(input).map(|result| result.to_string()),
+        _ => Result::Err("This problem only has 2 parts!".to_string())
     }
-
-    calculate_sum(&state, index_offset) as i32
 }
 
-pub fn solve_second<T: BufRead>(input: T) -> i64 {
-    let (mut state, rules) = get_input(input);
-    let mut last_state = state.iter().collect::<String>();
-    let mut index_offset = 0;
-    let mut last_index_offset = 0;
+/// `sum <generations...>` prints the pot sum after each of the given numbers of generations,
+/// `cycle` describes when the pots start repeating and how far they move each time.
+pub fn run_mode<T: BufRead>(mode: &str, args: &[&str], input: T) -> Result<String, String> {
+    let mut history = get_input(input)?;
 
-    let mut it = 0;
+    match mode {
+        "sum" => {
+            if args.is_empty() {
+                return Err("expected at least one generation".to_string());
+            }
 
-    loop {
-        run_step(&mut state, &rules, &mut index_offset);
-        it += 1;
-        let new_state = state.iter().collect::<String>();
+            let mut lines = Vec::new();
+            for arg in args {
+                let generations = arg.parse().map_err(|_| format!("invalid generation `{}`", arg))?;
+                lines.push(format!("{}: {}", generations, pot_sum(&mut history, generations)?));
+            }
 
-        if new_state == last_state {
-            break;
-        } else {
-            last_state = new_state.clone();
-            last_index_offset = index_offset;
+            Ok(lines.join("\n"))
         }
+        "cycle" => match history.find_cycle(MAX_GENERATIONS) {
+            Some(cycle) => Ok(format!(
+                "the pots repeat every {} generations from generation {} on, moving {} pots each time",
+                cycle.period, cycle.start, cycle.drift.0
+            )),
+            None => Err(format!("the pots don't repeat within {} generations", MAX_GENERATIONS))
+        },
+        _ => Result::Err(format!("Unknown mode `{}`, expected sum or cycle", mode))
     }
-
-    index_offset += (50000000000 - it) * (index_offset - last_index_offset);
-
-    calculate_sum(&state, index_offset)
 }
-
-pub fn solve<T: BufRead>(problem: u8, input: T) -> Result<String, String> {
-    match problem {
-        1 => Result::Ok(solve_first(input).to_string()),
-        2 => Result::Ok(solve_second(input).to_string()),
-        _ => Result::Err("This problem only has 2 parts!".to_string())
-    }
-}
\ No newline at end of file
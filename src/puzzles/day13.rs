@@ -1,7 +1,6 @@
 use std::cmp::Ordering;
-use std::collections::BTreeSet;
 use std::collections::HashMap;
-use std::collections::HashSet;
+use std::fmt;
 use std::io::BufRead;
 
 use utils;
@@ -19,7 +18,7 @@ impl Add for (i32, i32) {
     }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 enum Facing {
     Left,
     Right,
@@ -38,6 +37,25 @@ impl Facing {
         }
     }
 
+    fn name(&self) -> &'static str {
+        match self {
+            Facing::Left => "left",
+            Facing::Right => "right",
+            Facing::Up => "up",
+            Facing::Down => "down",
+        }
+    }
+
+    /// The side of a track piece a cart facing this way leaves through.
+    fn connection(&self) -> u8 {
+        match self {
+            Facing::Left => WEST,
+            Facing::Right => EAST,
+            Facing::Up => NORTH,
+            Facing::Down => SOUTH,
+        }
+    }
+
     fn direction(&self) -> (i32, i32) {
         match self {
             Facing::Left => (-1, 0),
@@ -61,6 +79,7 @@ impl Facing {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 enum RelativeFacing {
     Straight,
     Left,
@@ -68,6 +87,15 @@ enum RelativeFacing {
 }
 
 impl RelativeFacing {
+    fn parse(turn: char) -> Option<RelativeFacing> {
+        match turn {
+            'L' => Some(RelativeFacing::Left),
+            'S' => Some(RelativeFacing::Straight),
+            'R' => Some(RelativeFacing::Right),
+            _ => None,
+        }
+    }
+
     fn get_absolute(&self, direction: Facing) -> Facing {
         match self {
             RelativeFacing::Straight => direction,
@@ -87,30 +115,145 @@ impl RelativeFacing {
     }
 }
 
+/// Decides which way a cart goes at an intersection.
+trait TurnPolicy {
+    fn next_turn(&mut self) -> RelativeFacing;
+
+    /// Everything the policy's future turns depend on, so carts going around forever can be detected.
+    fn state(&self) -> u64;
+}
+
+/// Goes through the same sequence of turns over and over.
+struct Cycle {
+    turns: Vec<RelativeFacing>,
+    next: usize,
+}
+
+impl Cycle {
+    /// What the puzzle's carts do: left, straight, right, left, ...
+    fn puzzle() -> Cycle {
+        Cycle { turns: vec![RelativeFacing::Left, RelativeFacing::Straight, RelativeFacing::Right], next: 0 }
+    }
+}
+
+impl TurnPolicy for Cycle {
+    fn next_turn(&mut self) -> RelativeFacing {
+        let turn = self.turns[self.next];
+        self.next = (self.next + 1) % self.turns.len();
+
+        turn
+    }
+
+    fn state(&self) -> u64 {
+        self.next as u64
+    }
+}
+
+/// Turns every which way, using a xorshift generator so the same seed always gives the same turns.
+struct RandomTurns(u64);
+
+impl TurnPolicy for RandomTurns {
+    fn next_turn(&mut self) -> RelativeFacing {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+
+        [RelativeFacing::Left, RelativeFacing::Straight, RelativeFacing::Right][(self.0 % 3) as usize]
+    }
+
+    fn state(&self) -> u64 {
+        self.0
+    }
+}
+
+/// How a cart is configured to behave at intersections.
+#[derive(Clone, Debug)]
+enum PolicyConfig {
+    /// Turns to cycle through and the index of the first one.
+    Cycle(Vec<RelativeFacing>, usize),
+    Random(u64),
+}
+
+impl PolicyConfig {
+    /// Parses `LSR` (a cycle of turns), `LSR@2` (the same cycle starting with the third turn) or `random:<seed>`.
+    fn parse(text: &str) -> Result<PolicyConfig, String> {
+        if let Some(seed) = text.strip_prefix("random:") {
+            return seed.parse()
+                .map(|seed: u64| PolicyConfig::Random(seed.max(1)))
+                .map_err(|_| format!("invalid seed in `{}`", text));
+        }
+
+        let (turns, start) = match text.find('@') {
+            Some(index) => (&text[..index], text[index + 1..].parse().map_err(|_| format!("invalid start in `{}`", text))?),
+            None => (text, 0)
+        };
+        let turns: Vec<RelativeFacing> = turns.chars()
+            .map(|turn| RelativeFacing::parse(turn).ok_or_else(|| format!("unknown turn `{}`, expected L, S or R", turn)))
+            .collect::<Result<_, _>>()?;
+
+        if turns.is_empty() || start >= turns.len() {
+            return Err(format!("`{}` needs at least one turn and a start within the turns", text));
+        }
+
+        Ok(PolicyConfig::Cycle(turns, start))
+    }
+
+    fn build(&self) -> Box<dyn TurnPolicy> {
+        match self {
+            PolicyConfig::Cycle(turns, start) => Box::new(Cycle { turns: turns.clone(), next: *start }),
+            PolicyConfig::Random(seed) => Box::new(RandomTurns(*seed)),
+        }
+    }
+}
+
+/// Applies a side configuration like `["*=SR", "3=LSR@1", "4=random:7"]`, where `*` stands for all carts
+/// without their own entry.
+fn configure_carts(carts: &mut [Cart], config: &[&str]) -> Result<(), String> {
+    let mut default: Option<PolicyConfig> = None;
+    let mut per_cart: HashMap<usize, PolicyConfig> = HashMap::new();
+
+    for entry in config {
+        let index = entry.find('=').ok_or_else(|| format!("invalid cart configuration `{}`, expected `<cart>=<policy>`", entry))?;
+        let policy = PolicyConfig::parse(&entry[index + 1..])?;
+
+        match &entry[..index] {
+            "*" => default = Some(policy),
+            id => {
+                let id: usize = id.parse().map_err(|_| format!("invalid cart `{}`", id))?;
+                if id >= carts.len() {
+                    return Err(format!("there's no cart {}, carts are numbered from 0 to {}", id, carts.len().saturating_sub(1)));
+                }
+                per_cart.insert(id, policy);
+            }
+        }
+    }
+
+    for cart in carts.iter_mut() {
+        if let Some(policy) = per_cart.get(&cart.id).or(default.as_ref()) {
+            cart.policy = policy.build();
+        }
+    }
+
+    Ok(())
+}
+
 struct Cart {
+    /// Carts are numbered in reading order of their starting positions.
+    id: usize,
     position: (i32, i32),
     facing: Facing,
-    intersection_choice: RelativeFacing,
+    policy: Box<dyn TurnPolicy>,
+    /// Tick during which the cart crashed, if it did.
+    crashed: Option<u32>,
 }
 
 impl Cart {
-    fn new(position: (i32, i32), facing: Facing) -> Cart {
-        Cart { position, facing, intersection_choice: RelativeFacing::Left }
-    }
-
-    fn next_intersection_choice(&mut self) {
-        self.intersection_choice = match &self.intersection_choice {
-            RelativeFacing::Left => RelativeFacing::Straight,
-            RelativeFacing::Straight => RelativeFacing::Right,
-            RelativeFacing::Right => RelativeFacing::Left
-        }
+    fn new(id: usize, position: (i32, i32), facing: Facing) -> Cart {
+        Cart { id, position, facing, policy: Box::new(Cycle::puzzle()), crashed: None }
     }
 
     fn intersection_facing(&mut self) -> Facing {
-        let facing = self.intersection_choice.get_absolute(self.facing);
-        self.next_intersection_choice();
-
-        facing
+        self.policy.next_turn().get_absolute(self.facing)
     }
 
     fn next_position(&self) -> (i32, i32) {
@@ -131,8 +274,10 @@ enum Rail {
     Intersection,
 }
 
+type Grid = HashMap<(i32, i32), Rail>;
+
 
-fn sort_carts(carts: &mut Vec<Cart>) {
+fn sort_carts(carts: &mut [Cart]) {
     carts.sort_unstable_by(|x, y| {
         let (x1, y1) = x.position;
         let (x2, y2) = y.position;
@@ -147,10 +292,10 @@ fn sort_carts(carts: &mut Vec<Cart>) {
     });
 }
 
-fn move_cart(grid: &HashMap<(i32, i32), Rail>, cart: &mut Cart) {
+fn move_cart(grid: &Grid, cart: &mut Cart) {
     let next_pos = cart.next_position();
-    let rail = grid.get(&next_pos)
-        .expect("this cart isn't even on rails lol");
+    // the track was validated, every connection leads to another piece of track
+    let rail = &grid[&next_pos];
 
     let facing = match rail {
         Rail::Straight => cart.facing,
@@ -162,104 +307,371 @@ fn move_cart(grid: &HashMap<(i32, i32), Rail>, cart: &mut Cart) {
     cart.move_to(next_pos, facing);
 }
 
-fn simulate_until_crash(grid: &HashMap<(i32, i32), Rail>, carts: &mut Vec<Cart>) -> (i32, i32) {
-    loop {
-        sort_carts(carts);
+fn format_position(position: (i32, i32)) -> String {
+    format!("{},{}", position.0, position.1)
+}
 
-        let mut cart_positions: HashSet<(i32, i32)> = HashSet::new();
-        for cart in carts.iter_mut() {
-            move_cart(grid, cart);
+struct Collision {
+    /// Ticks are counted from 1.
+    tick: u32,
+    position: (i32, i32),
+    /// Ids of the cart that moved and the one it ran into.
+    carts: (usize, usize),
+}
 
-            if !cart_positions.insert(cart.position) {
-                return cart.position;
-            }
-        }
+/// Everything that happened until at most one cart was left.
+struct Timeline {
+    collisions: Vec<Collision>,
+    /// All carts, crashed or not, as they were at the end.
+    carts: Vec<Cart>,
+    ticks: u32,
+    /// Set if the carts that are left would keep going around without ever crashing.
+    endless: bool,
+}
+
+impl Timeline {
+    fn survivors(&self) -> Vec<&Cart> {
+        self.carts.iter().filter(|cart| cart.crashed.is_none()).collect()
     }
 }
 
-fn simulate_until_one_left(grid: &HashMap<(i32, i32), Rail>, carts: &mut Vec<Cart>) -> (i32, i32) {
-    while carts.len() > 1 {
-        sort_carts(carts);
+fn simulate(grid: &Grid, mut carts: Vec<Cart>) -> Timeline {
+    let mut collisions = Vec::new();
+    let mut ticks = 0;
+    // Brent's cycle detection: compare with a saved state which is replaced after every power of two ticks
+    let mut saved_state = None;
+    let mut power = 1;
+    let mut since_saved = 0;
+
+    while carts.iter().filter(|cart| cart.crashed.is_none()).count() > 1 {
+        // the id stands in for the cart's policy, which only exposes its progress through `state`
+        let mut state: Vec<(usize, (i32, i32), Facing, u64)> = carts.iter()
+            .filter(|cart| cart.crashed.is_none())
+            .map(|cart| (cart.id, cart.position, cart.facing, cart.policy.state()))
+            .collect();
+        state.sort_by_key(|&(id, ..)| id);
+        if saved_state.as_ref() == Some(&state) {
+            carts.sort_by_key(|cart| cart.id);
+            return Timeline { collisions, carts, ticks, endless: true };
+        }
 
-        let mut cart_positions: HashMap<(i32, i32), usize> = HashMap::new();
-        let mut to_remove: BTreeSet<usize> = BTreeSet::new();
+        since_saved += 1;
+        if since_saved == power {
+            saved_state = Some(state);
+            power *= 2;
+            since_saved = 0;
+        }
 
-        for (i, cart) in carts.iter_mut().enumerate() {
-            let other_index = cart_positions.remove(&cart.position);
-            move_cart(grid, cart);
+        ticks += 1;
+        sort_carts(&mut carts);
 
-            let other_index = other_index.or(cart_positions.remove(&cart.position));
+        let mut cart_positions: HashMap<(i32, i32), usize> = carts.iter()
+            .enumerate()
+            .filter(|(_, cart)| cart.crashed.is_none())
+            .map(|(i, cart)| (cart.position, i))
+            .collect();
 
-            if let Some(other_index) = other_index {
-                to_remove.insert(other_index);
-                to_remove.insert(i);
-            } else {
-                cart_positions.insert(cart.position, i);
+        for i in 0..carts.len() {
+            if carts[i].crashed.is_some() {
+                continue;
             }
-        }
 
-        for &i in to_remove.iter().rev() {
-            carts.remove(i);
+            cart_positions.remove(&carts[i].position);
+            move_cart(grid, &mut carts[i]);
+
+            match cart_positions.remove(&carts[i].position) {
+                Some(other) => {
+                    carts[i].crashed = Some(ticks);
+                    carts[other].crashed = Some(ticks);
+                    collisions.push(Collision { tick: ticks, position: carts[i].position, carts: (carts[i].id, carts[other].id) });
+                }
+                None => { cart_positions.insert(carts[i].position, i); }
+            }
         }
     }
 
-    carts.first().expect("even amount of carts, rip").position
+    carts.sort_by_key(|cart| cart.id);
+    Timeline { collisions, carts, ticks, endless: false }
 }
 
-fn parse_input<T: BufRead>(input: T) -> (HashMap<(i32, i32), Rail>, Vec<Cart>) {
-    let mut grid = HashMap::new();
-    let mut carts = Vec::new();
-    let lines = utils::get_lines(input);
+// Sides of a track piece, a piece is the set of sides it connects to.
+const NORTH: u8 = 1;
+const EAST: u8 = 2;
+const SOUTH: u8 = 4;
+const WEST: u8 = 8;
+const SIDES: [(u8, (i32, i32)); 4] = [(NORTH, (0, -1)), (EAST, (1, 0)), (SOUTH, (0, 1)), (WEST, (-1, 0))];
+
+const HORIZONTAL: u8 = EAST | WEST;
+const VERTICAL: u8 = NORTH | SOUTH;
+const JUNCTION: u8 = NORTH | EAST | SOUTH | WEST;
+/// `/` is either the top left or the bottom right corner of a loop.
+const CORNERS_UP: [u8; 2] = [EAST | SOUTH, NORTH | WEST];
+/// `\` is either the top right or the bottom left corner of a loop.
+const CORNERS_DOWN: [u8; 2] = [WEST | SOUTH, NORTH | EAST];
+
+fn opposite(side: u8) -> u8 {
+    match side {
+        NORTH => SOUTH,
+        EAST => WEST,
+        SOUTH => NORTH,
+        _ => EAST
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TrackErrorKind {
+    UnknownCharacter(char),
+    /// A rail leads somewhere without track that connects back.
+    DanglingRail,
+    /// An intersection which isn't connected on all four sides.
+    ImpossibleJunction,
+    /// None of the pieces the cart could be standing on fit in with its neighbours.
+    UnknownTrackUnderCart,
+    /// More than one piece fits in at this position.
+    AmbiguousTrack,
+}
 
-    let straight_rails = vec!['-', '|'];
-    let corner_up_rail = '/';
-    let corner_down_rail = '\\';
-    let intersection_rail = '+';
+#[derive(Debug, PartialEq)]
+pub struct TrackError {
+    pub x: usize,
+    pub y: usize,
+    pub kind: TrackErrorKind,
+}
+
+impl fmt::Display for TrackError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{},{}: ", self.x, self.y)?;
+        match self.kind {
+            TrackErrorKind::UnknownCharacter(c) => write!(f, "unknown character `{}`", c),
+            TrackErrorKind::DanglingRail => write!(f, "rail doesn't connect to anything"),
+            TrackErrorKind::ImpossibleJunction => write!(f, "intersection isn't connected on all sides"),
+            TrackErrorKind::UnknownTrackUnderCart => write!(f, "no track fits under the cart"),
+            TrackErrorKind::AmbiguousTrack => write!(f, "can't tell which piece of track this is"),
+        }
+    }
+}
+
+/// The pieces of track a character can stand for.
+fn candidate_pieces(c: char) -> Option<Vec<u8>> {
+    let pieces = match c {
+        ' ' => vec![0],
+        '-' => vec![HORIZONTAL],
+        '|' => vec![VERTICAL],
+        '+' => vec![JUNCTION],
+        '/' => CORNERS_UP.to_vec(),
+        '\\' => CORNERS_DOWN.to_vec(),
+        // a cart can be anywhere on the track, as long as it can go where it's facing
+        c => {
+            let facing = Facing::parse(c)?;
+            [HORIZONTAL, VERTICAL, JUNCTION].iter()
+                .chain(CORNERS_UP.iter())
+                .chain(CORNERS_DOWN.iter())
+                .cloned()
+                .filter(|&piece| piece & facing.connection() != 0)
+                .collect()
+        }
+    };
+
+    Some(pieces)
+}
+
+/// Works out which piece of track is at every position, including the ones hidden under carts.
+///
+/// A piece only fits if every side it connects to has a neighbour connecting back and every
+/// neighbour connecting to it is connected to in turn. Pieces that don't fit are discarded until
+/// everything that's left fits together.
+fn resolve_track(lines: &[Vec<char>]) -> Result<Vec<Vec<u8>>, Vec<TrackError>> {
+    let mut errors = Vec::new();
+    let mut candidates: Vec<Vec<Vec<u8>>> = Vec::new();
 
     for (y, line) in lines.iter().enumerate() {
-        for (x, c) in line.chars().enumerate() {
-            if c.is_whitespace() {
+        candidates.push(line.iter().enumerate()
+            .map(|(x, &c)| candidate_pieces(c).unwrap_or_else(|| {
+                errors.push(TrackError { x, y, kind: TrackErrorKind::UnknownCharacter(c) });
+                vec![0]
+            }))
+            .collect());
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let fits = |candidates: &Vec<Vec<Vec<u8>>>, x: usize, y: usize, piece: u8| SIDES.iter().all(|&(side, (dx, dy))| {
+        let neighbour = candidates.get((y as i32 + dy) as usize)
+            .and_then(|row| row.get((x as i32 + dx) as usize));
+        let connects = piece & side != 0;
+
+        match neighbour {
+            Some(pieces) => pieces.iter().any(|&other| (other & opposite(side) != 0) == connects),
+            None => !connects
+        }
+    });
+
+    // Pieces that don't fit with the track as drawn are what's actually wrong with it. Reporting
+    // what falls apart after removing them as well would bury those in follow-up errors.
+    for (y, row) in candidates.iter().enumerate() {
+        for (x, pieces) in row.iter().enumerate() {
+            // empty space only doesn't fit next to a rail that's broken itself
+            if lines[y][x] == ' ' || pieces.iter().any(|&piece| fits(&candidates, x, y, piece)) {
                 continue;
             }
 
+            let kind = match lines[y][x] {
+                '+' => TrackErrorKind::ImpossibleJunction,
+                '-' | '|' | '/' | '\\' => TrackErrorKind::DanglingRail,
+                _ => TrackErrorKind::UnknownTrackUnderCart
+            };
+            errors.push(TrackError { x, y, kind });
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+
+        for y in 0..candidates.len() {
+            for x in 0..candidates[y].len() {
+                let pieces: Vec<u8> = candidates[y][x].iter()
+                    .cloned()
+                    .filter(|&piece| fits(&candidates, x, y, piece))
+                    .collect();
+
+                if pieces.len() != candidates[y][x].len() {
+                    candidates[y][x] = pieces;
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    let mut track = Vec::new();
+    for (y, row) in candidates.into_iter().enumerate() {
+        let mut resolved = Vec::new();
+        for (x, pieces) in row.into_iter().enumerate() {
+            match pieces[..] {
+                [piece] => resolved.push(piece),
+                _ if lines[y][x] == ' ' => resolved.push(0),
+                [] => errors.push(TrackError { x, y, kind: TrackErrorKind::DanglingRail }),
+                _ => errors.push(TrackError { x, y, kind: TrackErrorKind::AmbiguousTrack }),
+            }
+        }
+        track.push(resolved);
+    }
+
+    if errors.is_empty() { Ok(track) } else { Err(errors) }
+}
+
+fn parse_input<T: BufRead>(input: T) -> Result<(Grid, Vec<Cart>), Vec<TrackError>> {
+    let mut lines: Vec<Vec<char>> = utils::get_lines_until_exit(input).iter()
+        .map(|line| line.chars().map(|c| if c.is_whitespace() { ' ' } else { c }).collect())
+        .collect();
+
+    // lines are padded with spaces on the right, but that doesn't have to be the case for all of them
+    while lines.last().is_some_and(|line| line.iter().all(|&c| c == ' ')) {
+        lines.pop();
+    }
+    let width = lines.iter().map(|line| line.len()).max().unwrap_or(0);
+    for line in lines.iter_mut() {
+        line.resize(width, ' ');
+    }
+
+    let track = resolve_track(&lines)?;
+    let mut grid = HashMap::new();
+    let mut carts = Vec::new();
+
+    for (y, row) in track.iter().enumerate() {
+        for (x, &piece) in row.iter().enumerate() {
             let pos = (x as i32, y as i32);
-            let rail: Rail;
-
-            if straight_rails.contains(&c) {
-                rail = Rail::Straight;
-            } else if c == corner_up_rail {
-                rail = Rail::CornerUp;
-            } else if c == corner_down_rail {
-                rail = Rail::CornerDown;
-            } else if c == intersection_rail {
-                rail = Rail::Intersection;
-            } else {
-                rail = Rail::Straight;
-                carts.push(Cart::new(pos, Facing::parse(c)
-                    .expect(format!("couldn't parse facing {}", c).as_str())));
+            let rail = match piece {
+                0 => continue,
+                HORIZONTAL | VERTICAL => Rail::Straight,
+                JUNCTION => Rail::Intersection,
+                piece if CORNERS_UP.contains(&piece) => Rail::CornerUp,
+                _ => Rail::CornerDown
+            };
+
+            if let Some(facing) = Facing::parse(lines[y][x]) {
+                carts.push(Cart::new(carts.len(), pos, facing));
             }
 
             grid.insert(pos, rail);
         }
     }
 
-    (grid, carts)
+    Ok((grid, carts))
 }
 
-pub fn solve_first<T: BufRead>(input: T) -> (i32, i32) {
-    let (grid, mut carts) = parse_input(input);
-    simulate_until_crash(&grid, &mut carts)
+fn get_input<T: BufRead>(input: T) -> Result<(Grid, Vec<Cart>), String> {
+    parse_input(input).map_err(|errors| {
+        errors.iter().map(|error| error.to_string()).collect::<Vec<String>>().join("\n")
+    })
 }
 
-pub fn solve_second<T: BufRead>(input: T) -> (i32, i32) {
-    let (grid, mut carts) = parse_input(input);
-    simulate_until_one_left(&grid, &mut carts)
+pub fn solve_first<T: BufRead>(input: T) -> Result<String, String> {
+    let (grid, carts) = get_input(input)?;
+
+    simulate(&grid, carts).collisions.first()
+        .map(|collision| format_position(collision.position))
+        .ok_or_else(|| "The carts never crash".to_string())
+}
+
+pub fn solve_second<T: BufRead>(input: T) -> Result<String, String> {
+    let (grid, carts) = get_input(input)?;
+    let timeline = simulate(&grid, carts);
+
+    match timeline.survivors()[..] {
+        [cart] => Ok(format_position(cart.position)),
+        [] => Err(format!("All carts crashed, the last ones after {} ticks", timeline.ticks)),
+        _ => Err("The carts that are left never crash".to_string())
+    }
 }
 
 pub fn solve<T: BufRead>(problem: u8, input: T) -> Result<String, String> {
     match problem {
-        1 => Result::Ok(format!("{:?}", solve_first(input))),
-        2 => Result::Ok(format!("{:?}", solve_second(input))),
+        1 => solve_first(input),
+        2 => solve_second(input),
         _ => Result::Err("This problem only has 2 parts!".to_string())
     }
-}
\ No newline at end of file
+}
+
+/// `timeline [carts...]` lists every collision and where all the carts ended up.
+/// Carts can be configured with `<cart>=<policy>` (`*` for all others), where the policy is a cycle
+/// of turns like `LSR`, optionally starting with a later turn like `LSR@1`, or `random:<seed>`.
+pub fn run_mode<T: BufRead>(mode: &str, args: &[&str], input: T) -> Result<String, String> {
+    match mode {
+        "timeline" => {
+            let (grid, mut carts) = get_input(input)?;
+            configure_carts(&mut carts, args)?;
+            let timeline = simulate(&grid, carts);
+
+            let mut lines: Vec<String> = timeline.collisions.iter()
+                .map(|collision| format!(
+                    "tick {}: cart {} ran into cart {} at {}",
+                    collision.tick, collision.carts.0, collision.carts.1, format_position(collision.position)
+                ))
+                .collect();
+
+            lines.push(match timeline.survivors().len() {
+                0 => format!("after {} ticks no carts are left", timeline.ticks),
+                1 => format!("after {} ticks one cart is left", timeline.ticks),
+                count if timeline.endless => format!("after {} ticks the {} carts that are left keep going forever", timeline.ticks, count),
+                count => format!("after {} ticks {} carts are left", timeline.ticks, count),
+            });
+
+            for cart in timeline.carts.iter() {
+                lines.push(match cart.crashed {
+                    Some(tick) => format!("cart {} crashed at {} during tick {}", cart.id, format_position(cart.position), tick),
+                    None => format!("cart {} is at {} facing {}", cart.id, format_position(cart.position), cart.facing.name()),
+                });
+            }
+
+            Ok(lines.join("\n"))
+        }
+        _ => Result::Err(format!("Unknown mode `{}`, expected timeline", mode))
+    }
+}
@@ -1,342 +1,595 @@
-use std::cmp::Ordering;
-use std::collections::hash_map::Entry;
+use std::collections::BTreeMap;
 use std::collections::HashMap;
-use std::collections::HashSet;
 use std::collections::VecDeque;
+use std::fmt;
 use std::io::BufRead;
-use std::iter::FromIterator;
+use std::iter;
 
 use rayon::prelude::*;
 
 use utils;
 
-#[derive(Clone, Debug, PartialEq)]
-enum Race {
-    Goblin,
-    Elf,
+const ELF: char = 'E';
+/// Elves with this much attack power kill a goblin with every hit, more can't make a difference.
+const MAX_ELF_AP: u32 = 200;
+
+/// Hit points and attack power the units of a faction start out with.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Stats {
+    pub hp: u32,
+    pub ap: u32,
+}
+
+/// Everything about a fight that isn't on the map.
+#[derive(Clone, Debug)]
+pub struct Rules {
+    /// Stats of every faction that doesn't have its own entry in `stats`.
+    pub default: Stats,
+    pub stats: HashMap<char, Stats>,
+    /// Whether units also hit adjacent units of their own faction.
+    pub friendly_fire: bool,
+}
+
+impl Default for Rules {
+    fn default() -> Rules {
+        Rules { default: Stats { hp: 200, ap: 3 }, stats: HashMap::new(), friendly_fire: false }
+    }
+}
+
+impl Rules {
+    pub fn stats(&self, faction: char) -> Stats {
+        self.stats.get(&faction).cloned().unwrap_or(self.default)
+    }
+
+    /// Parses rules like `E=200/3` (hp and ap of the elves), `*=300/5` (everyone else) and `friendly-fire`.
+    pub fn parse(args: &[&str]) -> Result<Rules, String> {
+        let mut rules = Rules::default();
+
+        for arg in args {
+            if *arg == "friendly-fire" {
+                rules.friendly_fire = true;
+                continue;
+            }
+
+            let invalid = || format!("invalid rule `{}`, expected `<faction>=<hp>/<ap>` or `friendly-fire`", arg);
+            let (faction, stats) = match arg.find('=') {
+                Some(index) => (&arg[..index], &arg[index + 1..]),
+                None => return Err(invalid())
+            };
+            let stats: Vec<u32> = stats.split('/')
+                .map(|value| value.parse().map_err(|_| invalid()))
+                .collect::<Result<_, _>>()?;
+            let stats = match stats[..] {
+                [hp, ap] => Stats { hp, ap },
+                _ => return Err(invalid())
+            };
+
+            let mut chars = faction.chars();
+            match (chars.next(), chars.next()) {
+                (Some('*'), None) => rules.default = stats,
+                (Some(faction), None) if is_faction(faction) => { rules.stats.insert(faction, stats); }
+                _ => return Err(invalid())
+            }
+        }
+
+        Ok(rules)
+    }
 }
 
 #[derive(Clone)]
 struct Unit {
-    race: Race,
+    faction: char,
     hp: u32,
-    ap: u16,
+    ap: u32,
     alive: bool,
-    x: u32,
-    y: u32,
+    /// Index into the map's tiles, which also happens to be the reading order.
+    pos: usize,
 }
 
 impl Unit {
-    fn new(x: u32, y: u32, race: Race) -> Unit {
-        Unit { x, y, race, hp: 200, ap: 3, alive: true }
+    fn new(pos: usize, faction: char, stats: Stats) -> Unit {
+        Unit { pos, faction, hp: stats.hp, ap: stats.ap, alive: true }
     }
 
-    fn positions_in_range(&self) -> Vec<Pos> {
-        get_neighbours((self.x, self.y)).to_vec()
-    }
-
-    fn take_hit(&mut self, assailant: &Unit) {
-        let ap = assailant.ap as u32;
+    /// Returns the damage that was actually dealt, i.e. no more than the hp the unit had left.
+    fn take_hit(&mut self, ap: u32) -> u32 {
         if self.hp > ap {
             self.hp -= ap;
+            ap
         } else {
+            let damage = self.hp;
             self.hp = 0;
             self.alive = false;
+            damage
         }
     }
-
-    fn move_to(&mut self, pos: Pos) {
-        let (x, y) = pos;
-        self.x = x;
-        self.y = y;
-    }
 }
 
-#[derive(PartialEq)]
+#[derive(Clone, Copy, PartialEq)]
 enum Tile {
     Wall,
     Open,
+    /// Outside of the map, e.g. whitespace or the end of a shorter line.
+    Void,
 }
 
-type Map = Vec<Vec<Tile>>;
-type Pos = (u32, u32);
-
-fn order_lexicographically(a: Pos, b: Pos) -> Ordering {
-    let y_cmp = a.1.cmp(&b.1);
-
-    if Ordering::Equal == y_cmp {
-        a.0.cmp(&b.0)
-    } else {
-        y_cmp
-    }
+struct Map {
+    width: usize,
+    tiles: Vec<Tile>,
 }
 
+impl Map {
+    fn is_open(&self, pos: usize) -> bool {
+        self.tiles[pos] == Tile::Open
+    }
 
-fn get_neighbours(pos: Pos) -> [Pos; 4] {
-    let (x, y) = pos;
-    [
-        (x, y - 1),
-        (x - 1, y),
-        (x + 1, y),
-        (x, y + 1)
-    ]
-}
-
-fn get_map_tile(map: &Map, pos: Pos) -> Option<&Tile> {
-    map.get(pos.1 as usize)
-        .and_then(|row: &Vec<Tile>| row.get(pos.0 as usize))
+    /// Positions next to `pos` in reading order.
+    fn neighbours(&self, pos: usize) -> [Option<usize>; 4] {
+        let x = pos % self.width;
+        [
+            pos.checked_sub(self.width),
+            if x > 0 { Some(pos - 1) } else { None },
+            if x + 1 < self.width { Some(pos + 1) } else { None },
+            Some(pos + self.width).filter(|&below| below < self.tiles.len()),
+        ]
+    }
 }
 
-fn sort_units(units: &mut Vec<Unit>) {
-    units.par_sort_unstable_by(|a, b| order_lexicographically((a.x, a.y), (b.x, b.y)));
+/// Breadth-first search from all squares in range of an enemy at once.
+///
+/// Every square ends up with its distance to the nearest of those squares and which one that is
+/// (the first in reading order on ties), so the square a unit moves to is simply its neighbour
+/// with the smallest distance and target. The buffers are reused for every turn.
+struct Pathfinder {
+    distance: Vec<u32>,
+    target: Vec<usize>,
+    /// Squares with the current generation have been visited by the current search.
+    visited: Vec<u32>,
+    generation: u32,
+    queue: VecDeque<usize>,
 }
 
-fn get_enemy_in_range<'a>(unit: &Unit, enemies: &'a mut [&'a mut Unit]) -> Option<&'a mut Unit> {
-    let positions: Vec<Pos> = unit.positions_in_range();
-
-    let mut potential: Vec<&mut Unit> = Vec::new();
-
-    for enemy in enemies.iter_mut() {
-        if positions.contains(&(enemy.x, enemy.y)) {
-            potential.push(enemy);
+impl Pathfinder {
+    fn new(size: usize) -> Pathfinder {
+        Pathfinder {
+            distance: vec![0; size],
+            target: vec![0; size],
+            visited: vec![0; size],
+            generation: 0,
+            queue: VecDeque::new(),
         }
     }
 
-
-    let enemy = potential.drain(..)
-        .min_by(|a, b| a.hp.cmp(&b.hp));
-
-    enemy
-}
-
-fn get_in_range_positions(targets: &[&Unit], map: &Map, occupied: &HashSet<Pos>) -> Vec<Pos> {
-    let mut positions = Vec::new();
-
-    for target in targets {
-        for pos in target.positions_in_range() {
-            if let Some(Tile::Open) = get_map_tile(map, pos) {
-                if !occupied.contains(&pos) {
-                    positions.push(pos);
-                }
+    /// Where the unit at `from` should step to get closer to the nearest of the `targets`.
+    fn next_step(&mut self, map: &Map, occupant: &[Option<usize>], from: usize, targets: &[usize]) -> Option<usize> {
+        self.generation += 1;
+        self.queue.clear();
+
+        for &target in targets {
+            if self.visited[target] != self.generation {
+                self.visited[target] = self.generation;
+                self.distance[target] = 0;
+                self.target[target] = target;
+                self.queue.push_back(target);
             }
         }
-    }
 
-    positions
-}
+        // once the whole layer next to the unit is known, nothing further away matters
+        let mut reached: Option<u32> = None;
 
-fn nearest_position_move(current: Pos, positions: &Vec<Pos>, map: &Map, occupied: &HashSet<Pos>) -> Option<Pos> {
-    let mut seen: HashSet<Pos> = HashSet::new();
-    let mut to_visit: VecDeque<(Pos, u32)> = VecDeque::from(vec![(current, 0)]);
-    let mut path: HashMap<Pos, (Pos, u32)> = HashMap::new();
-    path.insert(current, (current, 0));
-
-    while !to_visit.is_empty() {
-        let (pos, dist) = to_visit.pop_front().unwrap();
-        for &nb in get_neighbours(pos).iter() {
-            if let Some(ref tile) = get_map_tile(map, nb) {
-                if **tile == Tile::Wall || occupied.contains(&nb) {
-                    continue;
-                }
+        while let Some(pos) = self.queue.pop_front() {
+            let distance = self.distance[pos];
+            if reached.is_some_and(|reached| distance > reached) {
+                break;
+            }
 
-                match path.entry(nb) {
-                    Entry::Occupied(mut entry) => {
-                        let prev = entry.get_mut();
-                        if prev.1 > dist + 1 {
-                            *prev = (pos, dist + 1);
-                        }
-                    }
-                    Entry::Vacant(entry) => { entry.insert((pos, dist + 1)); }
+            for next in map.neighbours(pos).iter().filter_map(|&next| next) {
+                if next == from {
+                    reached = Some(reached.map_or(distance, |reached| reached.min(distance)));
                 }
 
-                if seen.contains(&nb) {
+                if !map.is_open(next) || occupant[next].is_some() {
                     continue;
                 }
 
-                if !to_visit.iter().any(|&visit| nb == visit.0) {
-                    to_visit.push_back((nb, dist + 1));
+                if self.visited[next] != self.generation {
+                    self.visited[next] = self.generation;
+                    self.distance[next] = distance + 1;
+                    self.target[next] = self.target[pos];
+                    self.queue.push_back(next);
+                } else if self.distance[next] == distance + 1 && self.target[pos] < self.target[next] {
+                    // squares in this layer haven't been expanded yet, so they can still switch targets
+                    self.target[next] = self.target[pos];
                 }
             }
         }
 
-        seen.insert(pos);
+        map.neighbours(from).iter()
+            .filter_map(|&next| next)
+            .filter(|&next| self.visited[next] == self.generation && occupant[next].is_none() && map.is_open(next))
+            .min_by_key(|&next| (self.distance[next], self.target[next], next))
     }
+}
 
-    let (_, mut target) = path.iter()
-        .filter_map(|(&pos, &(_, dist))| if positions.contains(&pos) { Some((dist, pos)) } else { None })
-        .min_by(|&a, &b| {
-            let (dist_a, pos_a) = a;
-            let (dist_b, pos_b) = b;
-            let dist_comp = dist_a.cmp(&dist_b);
-            if dist_comp == Ordering::Equal {
-                order_lexicographically(pos_a, pos_b)
-            } else {
-                dist_comp
-            }
-        })?;
+/// Renders the map like the puzzle does, with the hp of the units in each row next to it.
+fn render_game(map: &Map, units: &[Unit]) -> String {
+    let units: HashMap<usize, &Unit> = units.iter().filter(|u| u.alive).map(|u| (u.pos, u)).collect();
+    let mut lines = Vec::new();
 
-    loop {
-        if let Some(&(parent, dist)) = path.get(&target) {
-            if dist > 1 {
-                target = parent;
-                continue;
+    for (y, row) in map.tiles.chunks(map.width).enumerate() {
+        let mut line = String::new();
+        let mut annotations = Vec::new();
+
+        for (x, tile) in row.iter().enumerate() {
+            match (units.get(&(y * map.width + x)), tile) {
+                (Some(unit), _) => {
+                    line.push(unit.faction);
+                    annotations.push(format!("{}({})", unit.faction, unit.hp));
+                }
+                (None, Tile::Wall) => line.push('#'),
+                (None, Tile::Open) => line.push('.'),
+                (None, Tile::Void) => line.push(' ')
             }
         }
-        break;
+
+        line.push_str("   ");
+        line.push_str(&annotations.join(", "));
+        lines.push(line.trim_end().to_string());
     }
 
-    Some(target)
+    lines.join("\n")
 }
 
-fn get_next_move(current: Pos, targets: &[&Unit], map: &Map, occupied: &HashSet<Pos>) -> Option<Pos> {
-    let potential = get_in_range_positions(targets, map, occupied);
-    nearest_position_move(current, &potential, map, occupied)
+fn faction_name(faction: char) -> String {
+    match faction {
+        'E' => "Elves".to_string(),
+        'G' => "Goblins".to_string(),
+        _ => format!("Faction {}", faction)
+    }
 }
 
-#[allow(dead_code)]
-fn render_game(map: &Map, units: &Vec<Unit>) -> String {
-    let units: HashMap<Pos, &Unit> = HashMap::from_iter(units.iter().map(|u| ((u.x, u.y), u)));
-    let mut rendered = String::new();
+/// The puzzle's description of the fight: the map after every full round and how it ended.
+fn combat_log(map: &Map, units: &mut Vec<Unit>, rules: &Rules) -> String {
+    let mut log = vec![format!("Initially:\n{}", render_game(map, units))];
+    let outcome = simulate(map, units, rules, None, &mut |round, units| {
+        let title = if round == 1 { "After 1 round:".to_string() } else { format!("After {} rounds:", round) };
+        log.push(format!("{}\n{}", title, render_game(map, units)));
+    }).unwrap();
+
+    // units can still act in the round during which combat ends, so the final map can be different
+    let mut summary = vec![
+        format!("Final state:\n{}", render_game(map, units)),
+        format!("Combat ends after {} full rounds", outcome.rounds),
+    ];
+    for (&faction, report) in outcome.factions.iter().filter(|(_, report)| report.survivors > 0) {
+        summary.push(format!("{} win with {} total hit points left", faction_name(faction), report.hp));
+    }
+    summary.push(format!("Outcome: {} * {} = {}", outcome.rounds, outcome.hp(), outcome.score()));
+    log.push(summary.join("\n"));
 
-    for (y, row) in map.iter().enumerate() {
-        for (x, tile) in row.iter().enumerate() {
-            let mut tile_rendered = match tile {
-                Tile::Wall => '#',
-                Tile::Open => '.'
-            };
+    log.join("\n\n")
+}
 
-            if let Some(unit) = units.get(&(x as u32, y as u32)) {
-                if unit.alive {
-                    tile_rendered = match unit.race {
-                        Race::Elf => 'E',
-                        Race::Goblin => 'G'
-                    }
-                }
-            }
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FactionReport {
+    pub survivors: usize,
+    pub hp: u32,
+    pub damage_dealt: u32,
+    pub casualties: usize,
+}
 
-            rendered.push(tile_rendered);
-        }
-        rendered.push('\n');
+/// How a fight ended.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Outcome {
+    /// Number of full rounds that were fought.
+    pub rounds: u32,
+    pub factions: BTreeMap<char, FactionReport>,
+}
+
+impl Outcome {
+    /// Hit points left over all surviving units.
+    pub fn hp(&self) -> u32 {
+        self.factions.values().map(|report| report.hp).sum()
     }
 
-    rendered
+    pub fn score(&self) -> u32 {
+        self.rounds * self.hp()
+    }
 }
 
-fn simulate(map: &Map, units: &mut Vec<Unit>, abort_on_elf_death: bool) -> Option<u32> {
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Combat ends after {} full rounds", self.rounds)?;
+        for (faction, report) in self.factions.iter() {
+            writeln!(
+                f, "{}: {} survivors with {} hp left, dealt {} damage, lost {} units",
+                faction, report.survivors, report.hp, report.damage_dealt, report.casualties
+            )?;
+        }
+
+        write!(f, "Outcome: {} * {} = {}", self.rounds, self.hp(), self.score())
+    }
+}
+
+/// Fights until only one side is left. Gives up as soon as a unit of the `protected` faction dies.
+/// `after_round` is called with the number of the round and the units after every full round.
+fn simulate(map: &Map, units: &mut Vec<Unit>, rules: &Rules, protected: Option<char>, after_round: &mut dyn FnMut(u32, &[Unit])) -> Option<Outcome> {
     let mut rounds_passed: u32 = 0;
+    let mut factions: BTreeMap<char, FactionReport> = BTreeMap::new();
+    let mut alive: HashMap<char, usize> = HashMap::new();
+    for unit in units.iter().filter(|u| u.alive) {
+        factions.entry(unit.faction).or_default();
+        *alive.entry(unit.faction).or_insert(0) += 1;
+    }
+    let mut total_alive: usize = alive.values().sum();
+
+    let mut pathfinder = Pathfinder::new(map.tiles.len());
+    let mut occupant: Vec<Option<usize>> = vec![None; map.tiles.len()];
+    let mut targets: Vec<usize> = Vec::new();
 
     'simulation: loop {
-        sort_units(units);
+        units.retain(|u| u.alive);
+        units.sort_unstable_by_key(|u| u.pos);
+
+        occupant.iter_mut().for_each(|square| *square = None);
+        for (i, unit) in units.iter().enumerate() {
+            occupant[unit.pos] = Some(i);
+        }
 
         for i in 0..units.len() {
-            let mut done = false;
-            let mut unit = units.remove(i);
-
-            if unit.alive {
-                let occupied = HashSet::from_iter(units.iter()
-                    .filter_map(|u| if u.alive { Some((u.x, u.y)) } else { None }));
-
-                let mut targets: Vec<&mut Unit> = units.iter_mut()
-                    .filter(|u| u.alive && u.race != unit.race).collect();
-
-                if !targets.is_empty() {
-                    if let Some(target) = get_next_move(
-                        (unit.x, unit.y),
-                        targets.iter().map(|ref u| &***u)
-                            .collect::<Vec<&Unit>>().as_slice(),
-                        &map,
-                        &occupied) {
-                        unit.move_to(target);
-                    }
+            if !units[i].alive {
+                continue;
+            }
 
-                    if let Some(enemy) = get_enemy_in_range(&unit, &mut targets) {
-                        enemy.take_hit(&unit);
+            let faction = units[i].faction;
+            if total_alive == alive[&faction] {
+                break 'simulation;
+            }
 
-                        if abort_on_elf_death && enemy.race == Race::Elf && !enemy.alive {
-                            return None;
-                        }
-                    }
-                } else {
-                    done = true;
+            let is_enemy = |unit: &Unit| unit.faction != faction;
+            let enemy_adjacent = map.neighbours(units[i].pos).iter()
+                .filter_map(|&pos| pos.and_then(|pos| occupant[pos]))
+                .any(|j| is_enemy(&units[j]));
+
+            if !enemy_adjacent {
+                targets.clear();
+                for enemy in units.iter().filter(|u| u.alive && is_enemy(u)) {
+                    targets.extend(map.neighbours(enemy.pos).iter()
+                        .filter_map(|&pos| pos)
+                        .filter(|&pos| map.is_open(pos) && occupant[pos].is_none()));
+                }
+
+                if let Some(step) = pathfinder.next_step(map, &occupant, units[i].pos, &targets) {
+                    occupant[units[i].pos] = None;
+                    occupant[step] = Some(i);
+                    units[i].pos = step;
                 }
             }
 
-            units.insert(i, unit);
+            let victim = map.neighbours(units[i].pos).iter()
+                .filter_map(|&pos| pos.and_then(|pos| occupant[pos]))
+                .filter(|&j| rules.friendly_fire || is_enemy(&units[j]))
+                .min_by_key(|&j| (units[j].hp, units[j].pos));
 
-            if done {
-                break 'simulation;
+            if let Some(j) = victim {
+                let ap = units[i].ap;
+                factions.get_mut(&faction).unwrap().damage_dealt += units[j].take_hit(ap);
+
+                if !units[j].alive {
+                    let victim = &units[j];
+                    occupant[victim.pos] = None;
+                    factions.get_mut(&victim.faction).unwrap().casualties += 1;
+                    *alive.get_mut(&victim.faction).unwrap() -= 1;
+                    total_alive -= 1;
+
+                    if protected == Some(victim.faction) {
+                        return None;
+                    }
+                }
             }
         }
 
         rounds_passed += 1;
+        after_round(rounds_passed, units);
+    }
+
+    for unit in units.iter().filter(|u| u.alive) {
+        let report = factions.get_mut(&unit.faction).unwrap();
+        report.survivors += 1;
+        report.hp += unit.hp;
     }
 
-    Some(rounds_passed * units.iter().filter_map(|u| if u.alive { Some(u.hp) } else { None }).sum::<u32>())
+    Some(Outcome { rounds: rounds_passed, factions })
 }
 
-fn parse_input<T: BufRead>(input: T) -> (Map, Vec<Unit>) {
+/// Every character on the map that isn't a wall or open ground is a unit of the faction with that character.
+fn is_faction(c: char) -> bool {
+    c != '#' && c != '.' && !c.is_whitespace()
+}
+
+fn parse_input<T: BufRead>(input: T, rules: &Rules) -> (Map, Vec<Unit>) {
     let lines = utils::get_lines(input);
+    let width = lines.iter().map(|line| line.chars().count()).max().unwrap_or(0);
 
-    let mut map: Map = Vec::new();
+    let mut tiles = Vec::new();
     let mut units: Vec<Unit> = Vec::new();
 
-    for (y, line) in lines.iter().enumerate() {
-        let mut row = Vec::new();
-
-        for (x, c) in line.chars().enumerate() {
+    for line in lines.iter() {
+        for c in line.chars().chain(iter::repeat(' ')).take(width) {
             let tile = match c {
                 '#' => Tile::Wall,
+                c if c.is_whitespace() => Tile::Void,
                 _ => Tile::Open,
             };
 
-            row.push(tile);
-
-            let race = match c {
-                'G' => Some(Race::Goblin),
-                'E' => Some(Race::Elf),
-                _ => None
-            };
-
-            if let Some(race) = race {
-                units.push(Unit::new(x as u32, y as u32, race));
+            if is_faction(c) {
+                units.push(Unit::new(tiles.len(), c, rules.stats(c)));
             }
-        }
 
-        map.push(row);
+            tiles.push(tile);
+        }
     }
 
-    (map, units)
+    (Map { width, tiles }, units)
 }
 
 
 pub fn solve_first<T: BufRead>(input: T) -> u32 {
-    let (map, mut units) = parse_input(input);
-    simulate(&map, &mut units, false).unwrap()
+    let rules = Rules::default();
+    let (map, mut units) = parse_input(input, &rules);
+    simulate(&map, &mut units, &rules, None, &mut |_, _| {}).unwrap().score()
 }
 
-pub fn solve_second<T: BufRead>(input: T) -> u32 {
-    let (map, units) = parse_input(input);
-    let mut outcome: u32 = 0;
+/// Fights with the elves hitting as hard as `ap`, giving up once an elf dies if `protect_elves` is set.
+fn fight_with_elf_ap(map: &Map, units: &[Unit], rules: &Rules, ap: u32, protect_elves: bool) -> Option<Outcome> {
+    let mut rules = rules.clone();
+    let stats = Stats { ap, ..rules.stats(ELF) };
+    rules.stats.insert(ELF, stats);
 
-    for ap in 4..100 {
-        let mut units = units.clone();
-        units.iter_mut()
-            .filter(|u| u.race == Race::Elf)
-            .for_each(|elf| elf.ap = ap);
+    let mut units = units.to_vec();
+    units.iter_mut()
+        .filter(|u| u.faction == ELF)
+        .for_each(|elf| elf.ap = ap);
 
-        if let Some(result) = simulate(&map, &mut units, true) {
-            outcome = result;
+    simulate(map, &mut units, &rules, if protect_elves { Some(ELF) } else { None }, &mut |_, _| {})
+}
+
+/// Runs the fights for all the given powers at once, without elf casualties or `None`.
+fn try_elf_aps(map: &Map, units: &[Unit], rules: &Rules, aps: &[u32]) -> Vec<(u32, Option<Outcome>)> {
+    aps.par_iter()
+        .map(|&ap| (ap, fight_with_elf_ap(map, units, rules, ap, true)))
+        .collect()
+}
+
+/// The smallest attack power above the one from `rules` with which no elf dies.
+///
+/// More attack power almost always means fewer losses, so we narrow the range down by trying as
+/// many powers at once as there are threads. Because that isn't guaranteed, the powers right below
+/// the result are tried as well and if one of those works after all, we fall back to trying them all.
+fn find_min_elf_ap(map: &Map, units: &[Unit], rules: &Rules, limit: u32) -> Result<(u32, Outcome), String> {
+    let threads = rayon::current_num_threads().max(2) as u32;
+    let start = rules.stats(ELF).ap + 1;
+    let no_power_works = || format!("The elves can't win without losses with up to {} attack power", limit);
+    let first_success = |results: Vec<(u32, Option<Outcome>)>| results.into_iter()
+        .filter_map(|(ap, outcome)| outcome.map(|outcome| (ap, outcome)))
+        .next();
+
+    if limit < start {
+        return Err(no_power_works());
+    }
+
+    // everything up to `low` is assumed to fail, `found` is the lowest power known to work
+    let mut low = start - 1;
+    let mut found: Option<(u32, Outcome)> = None;
+
+    loop {
+        let high = found.as_ref().map_or(limit + 1, |&(ap, _)| ap);
+        if high - low <= 1 {
             break;
         }
+
+        let mut probes: Vec<u32> = (1..=threads)
+            .map(|i| low + ((high - low) as u64 * i as u64 / (threads + 1) as u64) as u32)
+            .filter(|&ap| ap > low && ap < high)
+            .collect();
+        probes.dedup();
+        if probes.is_empty() {
+            break;
+        }
+
+        let results = try_elf_aps(map, units, rules, &probes);
+        match first_success(results) {
+            Some((ap, outcome)) => {
+                low = probes.iter().cloned().filter(|&probe| probe < ap).max().unwrap_or(low);
+                found = Some((ap, outcome));
+            }
+            None => low = probes[probes.len() - 1]
+        }
     }
 
-    outcome
+    let (ap, outcome) = found.ok_or_else(no_power_works)?;
+
+    let below: Vec<u32> = (ap.saturating_sub(threads).max(start)..ap).collect();
+    if first_success(try_elf_aps(map, units, rules, &below)).is_none() {
+        return Ok((ap, outcome));
+    }
+
+    let all: Vec<u32> = (start..ap).collect();
+    for chunk in all.chunks(threads as usize) {
+        if let Some(result) = first_success(try_elf_aps(map, units, rules, chunk)) {
+            return Ok(result);
+        }
+    }
+
+    unreachable!("a power below {} works, but none was found", ap)
+}
+
+/// Outcome of the full fight for every elf attack power from the one in `rules` up to `limit`.
+fn elf_ap_table(map: &Map, units: &[Unit], rules: &Rules, limit: u32) -> Vec<(u32, Outcome)> {
+    let aps: Vec<u32> = (rules.stats(ELF).ap..=limit).collect();
+
+    aps.par_iter()
+        .map(|&ap| (ap, fight_with_elf_ap(map, units, rules, ap, false).unwrap()))
+        .collect()
+}
+
+pub fn solve_second<T: BufRead>(input: T) -> Result<u32, String> {
+    let rules = Rules::default();
+    let (map, units) = parse_input(input, &rules);
+
+    find_min_elf_ap(&map, &units, &rules, MAX_ELF_AP).map(|(_, outcome)| outcome.score())
 }
 
 pub fn solve<T: BufRead>(problem: u8, input: T) -> Result<String, String> {
     match problem {
         1 => Result::Ok(solve_first(input).to_string()),
-        2 => Result::Ok(solve_second(input).to_string()),
+        2 => solve_second(input).map(|result| result.to_string()),
         _ => Result::Err("This problem only has 2 parts!".to_string())
     }
-}
\ No newline at end of file
+}
+
+/// `combat [rules...]` fights with custom rules and reports on every faction.
+/// Rules are `<faction>=<hp>/<ap>` (`*` for all factions without their own rule) and `friendly-fire`.
+/// `log [rules...]` prints the map after every round the way the puzzle's examples do.
+/// `elf-power [limit]` finds the attack power the elves need to win without losses and
+/// `elf-power-table [limit]` lists the outcome of the fight for every attack power up to `limit`.
+pub fn run_mode<T: BufRead>(mode: &str, args: &[&str], input: T) -> Result<String, String> {
+    let limit = match args.first() {
+        Some(limit) if mode.starts_with("elf-power") => limit.parse().map_err(|_| format!("invalid attack power limit `{}`", limit))?,
+        _ => MAX_ELF_AP
+    };
+
+    match mode {
+        "combat" => {
+            let rules = Rules::parse(args)?;
+            let (map, mut units) = parse_input(input, &rules);
+            let outcome = simulate(&map, &mut units, &rules, None, &mut |_, _| {}).unwrap();
+            Ok(outcome.to_string())
+        }
+        "log" => {
+            let rules = Rules::parse(args)?;
+            let (map, mut units) = parse_input(input, &rules);
+            Ok(combat_log(&map, &mut units, &rules))
+        }
+        "elf-power" => {
+            let rules = Rules::default();
+            let (map, units) = parse_input(input, &rules);
+            let (ap, outcome) = find_min_elf_ap(&map, &units, &rules, limit)?;
+            Ok(format!("Elves need {} attack power\n{}", ap, outcome))
+        }
+        "elf-power-table" => {
+            let rules = Rules::default();
+            let (map, units) = parse_input(input, &rules);
+
+            let lines: Vec<String> = elf_ap_table(&map, &units, &rules, limit).iter()
+                .map(|(ap, outcome)| format!(
+                    "{:>4}: {} * {} = {}, elves lost: {}",
+                    ap, outcome.rounds, outcome.hp(), outcome.score(),
+                    outcome.factions.get(&ELF).map_or(0, |report| report.casualties)
+                ))
+                .collect();
+            Ok(lines.join("\n"))
+        }
+        _ => Result::Err(format!("Unknown mode `{}`, expected combat, log, elf-power or elf-power-table", mode))
+    }
+}
@@ -1,37 +1,23 @@
-use std::collections::HashMap;
-use std::collections::HashSet;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
 use std::io::BufRead;
-use std::iter::FromIterator;
 
 use regex::Regex;
 
+use elfcode::{Arithmetic, Instruction, Opcode, Overflow, Program, Width};
 use utils;
 
-type Register = Vec<u16>;
-type Operation = (u16, u16, u16, u16);
+type Register = Vec<usize>;
+type Operation = (usize, usize, usize, usize);
 type Sample = (Register, Operation, Register);
-type OpMap = HashMap<&'static str, Op>;
-
-macro_rules! hashmap {
-    ($( $key: expr => $val: expr ),*) => {{
-         let mut map = ::std::collections::HashMap::new();
-         $( map.insert($key, $val); )*
-         map
-    }}
-}
+type Candidates = BTreeMap<usize, BTreeSet<Opcode>>;
+type Mapping = BTreeMap<usize, Opcode>;
 
-struct Op {
-    op: Box<Fn(&Register, u16, u16) -> u16>
-}
+/// The device has 16-bit registers, anything that doesn't fit is an error.
+const ARITHMETIC: Arithmetic = Arithmetic { width: Width::W16, overflow: Overflow::Trap };
 
-impl Op {
-    fn new(op: Box<Fn(&Register, u16, u16) -> u16>) -> Op {
-        Op { op }
-    }
-    fn perform(&self, register: &mut Register, a: u16, b: u16, c: usize) {
-        register[c] = (self.op)(register, a, b);
-    }
-}
+/// Upper bound for the number of consistent mappings we bother enumerating.
+const MAX_MAPPINGS: usize = 100;
 
 fn parse_sample(sample: &str) -> Option<Sample> {
     let number_extractor = Regex::new(r#"\[(\d+), *(\d+), *(\d+), *(\d+)]"#).unwrap();
@@ -45,14 +31,14 @@ fn parse_sample(sample: &str) -> Option<Sample> {
             .collect();
 
         let mut it = raw_op.split_whitespace().map(|n| n.parse().unwrap());
-        let mut op: Operation = (it.next().unwrap(), it.next().unwrap(), it.next().unwrap(), it.next().unwrap());
+        let op: Operation = (it.next().unwrap(), it.next().unwrap(), it.next().unwrap(), it.next().unwrap());
 
         let after: Register = number_extractor
             .captures(after)?
             .iter()
             .skip(1)
             .map(|n| n.unwrap().as_str().parse().unwrap())
-            .collect::<Vec<u16>>();
+            .collect::<Register>();
 
         Some((before, op, after))
     } else {
@@ -87,55 +73,121 @@ fn get_input<T: BufRead>(input: T) -> Option<(Vec<Sample>, Vec<Operation>)> {
     }
 }
 
-fn get_opcodes() -> OpMap {
-    hashmap![
-        "addr" => Op::new(Box::new(|register: &Register, a, b| register[a as usize] + register[b as usize])),
-        "addi" => Op::new(Box::new(|register: &Register, a, b| register[a as usize] + b)),
-        "mulr" => Op::new(Box::new(|register: &Register, a, b| register[a as usize] * register[b as usize])),
-        "muli" => Op::new(Box::new(|register: &Register, a, b| register[a as usize] * b)),
-        "banr" => Op::new(Box::new(|register: &Register, a, b| register[a as usize] & register[b as usize])),
-        "bani" => Op::new(Box::new(|register: &Register, a, b| register[a as usize] & b)),
-        "borr" => Op::new(Box::new(|register: &Register, a, b| register[a as usize] | register[b as usize])),
-        "bori" => Op::new(Box::new(|register: &Register, a, b| register[a as usize] | b)),
-        "setr" => Op::new(Box::new(|register: &Register, a, _| register[a as usize])),
-        "seti" => Op::new(Box::new(|_, a, _| a)),
-        "gtir" => Op::new(Box::new(|register: &Register, a, b| (a > register[b as usize]) as u16)),
-        "gtri" => Op::new(Box::new(|register: &Register, a, b| (register[a as usize] > b) as u16)),
-        "gtrr" => Op::new(Box::new(|register: &Register, a, b| (register[a as usize] > register[b as usize]) as u16)),
-        "eqir" => Op::new(Box::new(|register: &Register, a, b| (a == register[b as usize]) as u16)),
-        "eqri" => Op::new(Box::new(|register: &Register, a, b| (register[a as usize] == b) as u16)),
-        "eqrr" => Op::new(Box::new(|register: &Register, a, b| (register[a as usize] == register[b as usize]) as u16))
-    ]
+fn to_instruction(operation: &Operation, opcode: Opcode) -> Instruction {
+    Instruction::new(opcode, operation.1, operation.2, operation.3)
 }
 
-fn filter_impossible_opcodes(sample: &Sample, possible_ops: &mut HashSet<&str>, opcode_map: &OpMap) {
+/// Removes all opcodes that don't turn the sample's before state into its after state.
+/// Returns the opcodes that were removed.
+fn filter_impossible_opcodes(sample: &Sample, possible_ops: &mut BTreeSet<Opcode>) -> Vec<Opcode> {
     let (before, operation, after) = sample;
-    let mut invalid = HashSet::new();
 
-    for &possible_op in possible_ops.iter() {
-        let op = opcode_map.get(possible_op).unwrap();
+    let invalid: Vec<Opcode> = possible_ops.iter()
+        .cloned()
+        .filter(|&opcode| {
+            let mut register = before.clone();
+            match to_instruction(operation, opcode).execute(&mut register, &ARITHMETIC) {
+                Ok(()) => &register != after,
+                Err(_) => true
+            }
+        })
+        .collect();
+
+    invalid.iter().for_each(|op_code| { possible_ops.remove(op_code); });
+    invalid
+}
+
+struct Deduction {
+    candidates: Candidates,
+    /// For every opcode number and ruled out mnemonic: the first sample which ruled it out and how many did in total.
+    eliminations: BTreeMap<(usize, Opcode), (usize, usize)>,
+}
+
+fn deduce(samples: &[Sample]) -> Deduction {
+    let mut candidates: Candidates = (0..Opcode::ALL.len())
+        .map(|number| (number, Opcode::ALL.iter().cloned().collect()))
+        .collect();
+    let mut eliminations = BTreeMap::new();
 
-        let mut register = before.clone();
-        op.perform(&mut register, operation.1, operation.2, operation.3 as usize);
+    for (index, sample) in samples.iter().enumerate() {
+        let number = (sample.1).0;
+        let mut possible_ops = Opcode::ALL.iter().cloned().collect();
 
-        if &register != after {
-            invalid.insert(possible_op);
+        for opcode in filter_impossible_opcodes(sample, &mut possible_ops) {
+            candidates.entry(number).or_default().remove(&opcode);
+            eliminations.entry((number, opcode)).or_insert((index, 0)).1 += 1;
         }
     }
 
-    invalid.iter().for_each(|&op_code| { possible_ops.remove(op_code); });
+    Deduction { candidates, eliminations }
+}
+
+/// Finds every assignment of distinct mnemonics to opcode numbers that agrees with the candidates,
+/// stopping after `limit` of them.
+fn consistent_mappings(candidates: &Candidates, limit: usize) -> Vec<Mapping> {
+    // the most constrained numbers first keeps the search small
+    let mut numbers: Vec<usize> = candidates.keys().cloned().collect();
+    numbers.sort_by_key(|number| candidates[number].len());
+
+    fn search(numbers: &[usize], candidates: &Candidates, mapping: &mut Mapping, used: &mut BTreeSet<Opcode>, found: &mut Vec<Mapping>, limit: usize) {
+        if found.len() >= limit {
+            return;
+        }
+
+        let (number, rest) = match numbers.split_first() {
+            Some(split) => split,
+            None => {
+                found.push(mapping.clone());
+                return;
+            }
+        };
+
+        for &opcode in candidates[number].iter() {
+            if used.insert(opcode) {
+                mapping.insert(*number, opcode);
+                search(rest, candidates, mapping, used, found, limit);
+                mapping.remove(number);
+                used.remove(&opcode);
+            }
+        }
+    }
+
+    let mut found = Vec::new();
+    search(&numbers, candidates, &mut BTreeMap::new(), &mut BTreeSet::new(), &mut found, limit);
+    found
+}
+
+fn find_mapping(samples: &[Sample]) -> Result<Mapping, String> {
+    let deduction = deduce(samples);
+    let mut mappings = consistent_mappings(&deduction.candidates, 2);
+
+    match mappings.len() {
+        1 => Ok(mappings.remove(0)),
+        0 => Err("No mapping of opcodes is consistent with the samples!".to_string()),
+        _ => Err("Input ambiguous! More than one mapping of opcodes fits the samples (try the `mapping` mode).".to_string())
+    }
+}
+
+fn translate(program: &[Operation], mapping: &Mapping) -> Result<Vec<Instruction>, String> {
+    program.iter()
+        .enumerate()
+        .map(|(address, operation)| {
+            let opcode = mapping.get(&operation.0)
+                .ok_or_else(|| format!("instruction {} uses unknown opcode {}", address, operation.0))?;
+            Ok(to_instruction(operation, *opcode))
+        })
+        .collect()
 }
 
 
 pub fn solve_first<T: BufRead>(input: T) -> usize {
-    let opcodes = get_opcodes();
     let (samples, _) = get_input(input).expect("Invalid input?");
 
     let mut more_than_three = 0;
 
     for sample in samples {
-        let mut possible_ops = opcodes.keys().map(|&s| s).collect();
-        filter_impossible_opcodes(&sample, &mut possible_ops, &opcodes);
+        let mut possible_ops = Opcode::ALL.iter().cloned().collect();
+        filter_impossible_opcodes(&sample, &mut possible_ops);
 
         if possible_ops.len() >= 3 {
             more_than_three += 1;
@@ -145,56 +197,77 @@ pub fn solve_first<T: BufRead>(input: T) -> usize {
     more_than_three
 }
 
-pub fn solve_second<T: BufRead>(input: T) -> u16 {
-    let opcodes = get_opcodes();
-    let mut opcode_map: HashMap<u16, HashSet<&str>> = HashMap::new();
-    for i in 0..16 {
-        opcode_map.insert(i, opcodes.keys().map(|&s| s).collect());
-    }
-
-    let (samples, program) = get_input(input).expect("Invalid input?");
+pub fn solve_second<T: BufRead>(input: T) -> Result<usize, String> {
+    let (samples, program) = get_input(input).ok_or("Invalid input?")?;
+    let mapping = find_mapping(&samples)?;
 
-    for sample in samples {
-        let opcode = (sample.1).0;
-        let mut possible_ops = opcode_map.get_mut(&opcode).unwrap();
+    let mut register: Register = vec![0; 4];
 
-        filter_impossible_opcodes(&sample, &mut possible_ops, &opcodes);
+    for (address, instruction) in translate(&program, &mapping)?.iter().enumerate() {
+        instruction.execute(&mut register, &ARITHMETIC)
+            .map_err(|kind| instruction.fault(address, kind).to_string())?;
     }
 
-    let mut ambiguous = true;
-    while ambiguous {
-        let reserved: HashSet<&str> = opcode_map.values().filter_map(|v| if v.len() == 1 { Some(*v.iter().next().unwrap()) } else { None }).collect();
-        if reserved.is_empty() {
-            panic!("Input ambiguous!");
-        }
+    Ok(register[0])
+}
+
+pub fn solve<T: BufRead>(problem: u8, input: T) -> Result<String, String> {
+    match problem {
+        1 => Result::Ok(solve_first(input).to_string()),
+        2 => solve_second(input).map(|result| result.to_string()),
+        _ => Result::Err("This problem only has 2 parts!".to_string())
+    }
+}
 
-        ambiguous = false;
-        for (_, value) in opcode_map.iter_mut() {
-            if value.len() > 1 {
-                ambiguous = true;
+fn format_mapping(mapping: &Mapping) -> String {
+    mapping.iter()
+        .map(|(number, opcode)| format!("{:>2} -> {}", number, opcode))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
 
-                for &res in reserved.iter() {
-                    value.remove(res);
+/// `mapping` prints the number to mnemonic table (or every table that fits the samples),
+/// `explain` lists which samples ruled out which mnemonics and `translate` prints the test program
+/// with mnemonics so it can be run with day 19.
+pub fn run_mode<T: BufRead>(mode: &str, _args: &[&str], input: T) -> Result<String, String> {
+    let (samples, program) = get_input(input).ok_or("Invalid input?")?;
+
+    match mode {
+        "mapping" => {
+            let deduction = deduce(&samples);
+            let mappings = consistent_mappings(&deduction.candidates, MAX_MAPPINGS);
+
+            match mappings.len() {
+                0 => Err("No mapping of opcodes is consistent with the samples!".to_string()),
+                1 => Ok(format_mapping(&mappings[0])),
+                count => {
+                    let qualifier = if count == MAX_MAPPINGS { "at least " } else { "" };
+                    let tables: Vec<String> = mappings.iter().map(format_mapping).collect();
+                    Ok(format!("{}{} consistent mappings:\n\n{}", qualifier, count, tables.join("\n\n")))
                 }
             }
         }
-    }
+        "explain" => {
+            let deduction = deduce(&samples);
+            let mut lines = Vec::new();
 
-    let opcode_map: HashMap<u16, &str> = HashMap::from_iter(opcode_map.iter().map(|(&k, v)| (k, *v.iter().next().expect("there's no opcode rip"))));
-    let mut register: Register = vec![0; 4];
+            for (number, candidates) in deduction.candidates.iter() {
+                let remaining: Vec<&str> = candidates.iter().map(|op| op.mnemonic()).collect();
+                lines.push(format!("opcode {} could be: {}", number, remaining.join(", ")));
 
-    for instruction in program {
-        let op = opcodes.get(*opcode_map.get(&instruction.0).unwrap()).unwrap();
-        op.perform(&mut register, instruction.1, instruction.2, instruction.3 as usize);
-    }
+                for (&(_, opcode), &(first, count)) in deduction.eliminations.range((*number, Opcode::ALL[0])..=(*number, Opcode::ALL[15])) {
+                    lines.push(format!("    not {}: ruled out by {} sample(s), first by sample {}", opcode, count, first + 1));
+                }
+            }
 
-    register[0]
-}
+            Ok(lines.join("\n"))
+        }
+        "translate" => {
+            let mapping = find_mapping(&samples)?;
 
-pub fn solve<T: BufRead>(problem: u8, input: T) -> Result<String, String> {
-    match problem {
-        1 => Result::Ok(solve_first(input).to_string()),
-        2 => Result::Ok(solve_second(input).to_string()),
-        _ => Result::Err("This problem only has 2 parts!".to_string())
+            // the test program only uses registers 0 - 3, so binding the ip to register 4 doesn't change anything
+            Ok(Program { ip: 4, instructions: translate(&program, &mapping)? }.to_string())
+        }
+        _ => Result::Err(format!("Unknown mode `{}`, expected mapping, explain or translate", mode))
     }
 }
\ No newline at end of file
@@ -1,229 +1,322 @@
-use std::collections::HashSet;
-use std::hash::{
-    BuildHasher,
-    Hash,
-};
 use std::io::BufRead;
-use std::usize;
 
 use regex::Regex;
 
 use utils;
 
 type Position = (usize, usize);
-type TileSet = HashSet<Position>;
-type Map = (TileSet, TileSet, TileSet);
 type BoundingBox = (Position, Position);
 
+const SPRING: Position = (500, 0);
 
-pub fn hashset_take_arbitrary<K, S>(set: &mut HashSet<K, S>) -> Option<K>
-    where K: Hash + Eq, S: BuildHasher
-{
-    let key_ref = {
-        if let Some(key_ref) = set.iter().next() {
-            unsafe { &*(key_ref as *const _) }
-        } else {
-            return None;
-        }
-    };
-    set.take(key_ref)
+#[derive(Clone, Copy, PartialEq)]
+enum Tile {
+    Sand,
+    Clay,
+    Flowing,
+    Settled,
 }
 
-fn get_map<T: BufRead>(input: T) -> (Map, BoundingBox) {
-    let x_first_parser = Regex::new(r#"(?:x=(?P<x_start>\d+), y=(?P<y_start>\d+)(?:\.{2}(?P<y_end>\d+))?)"#).unwrap();
-    let y_first_parser = Regex::new(r#"(?:y=(?P<y_start>\d+), x=(?P<x_start>\d+)(?:\.{2}(?P<x_end>\d+))?)"#).unwrap();
+impl Tile {
+    /// Whether water can rest on top of this tile.
+    fn is_solid(self) -> bool {
+        self == Tile::Clay || self == Tile::Settled
+    }
+}
 
-    let mut clay_tiles = HashSet::new();
-    let mut top_left = (usize::MAX, usize::MAX);
-    let mut bottom_right = (usize::MIN, usize::MIN);
+/// Dense grid covering the clay and the springs, with an extra column on either side for water
+/// flowing past the outermost clay.
+struct Map {
+    tiles: Vec<Tile>,
+    /// Position of the top left tile.
+    origin: Position,
+    width: usize,
+}
 
-    let lines = utils::get_lines(input);
-    for line in lines.iter() {
-        let captures = x_first_parser.captures(line)
-            .or(y_first_parser.captures(line))
-            .expect("line didn't match format");
-
-        let x_start = captures.name("x_start")
-            .unwrap().as_str().parse()
-            .unwrap();
-        let x_end = captures.name("x_end")
-            .and_then(|m| Some(m.as_str().parse().unwrap()))
-            .unwrap_or(x_start);
-
-        let y_start = captures.name("y_start")
-            .unwrap().as_str().parse()
-            .unwrap();
-        let y_end = captures.name("y_end")
-            .and_then(|m| Some(m.as_str().parse().unwrap()))
-            .unwrap_or(y_start);
-
-        if x_start < top_left.0 {
-            top_left.0 = x_start;
+impl Map {
+    fn new(clay: &[Position], springs: &[Position]) -> Map {
+        let all = || clay.iter().chain(springs.iter());
+        let min_x = all().map(|pos| pos.0).min().unwrap_or(0);
+        let max_x = all().map(|pos| pos.0).max().unwrap_or(0);
+        let min_y = all().map(|pos| pos.1).min().unwrap_or(0);
+        let max_y = all().map(|pos| pos.1).max().unwrap_or(0);
+
+        let origin = (min_x.saturating_sub(1), min_y);
+        let width = max_x + 2 - origin.0;
+        let height = max_y + 1 - min_y;
+
+        let mut map = Map { tiles: vec![Tile::Sand; width * height], origin, width };
+        for &pos in clay {
+            let index = map.index(pos);
+            map.tiles[index] = Tile::Clay;
         }
-        if x_end > bottom_right.0 {
-            bottom_right.0 = x_end;
+
+        map
+    }
+
+    fn index(&self, pos: Position) -> usize {
+        (pos.1 - self.origin.1) * self.width + pos.0 - self.origin.0
+    }
+
+    fn position(&self, index: usize) -> Position {
+        (self.origin.0 + index % self.width, self.origin.1 + index / self.width)
+    }
+
+    fn below(&self, index: usize) -> Option<usize> {
+        Some(index + self.width).filter(|&below| below < self.tiles.len())
+    }
+
+    /// Turns the flowing water at `index` into settled water if it's part of a row of flowing water
+    /// that has clay on both ends and lies on top of something solid. Returns the row if it settled.
+    fn try_settle(&mut self, index: usize) -> Option<(usize, usize)> {
+        let row_start = index - index % self.width;
+        let resting = |map: &Map, i: usize| map.tiles[i] == Tile::Flowing
+            && map.below(i).is_some_and(|below| map.tiles[below].is_solid());
+
+        let mut left = index;
+        while left > row_start && resting(self, left) {
+            left -= 1;
         }
-        if y_start < top_left.1 {
-            top_left.1 = y_start;
+        let mut right = index;
+        while right < row_start + self.width - 1 && resting(self, right) {
+            right += 1;
         }
-        if y_end > bottom_right.1 {
-            bottom_right.1 = y_end;
+
+        if self.tiles[left] != Tile::Clay || self.tiles[right] != Tile::Clay {
+            return None;
         }
 
-        for x in x_start..=x_end {
-            for y in y_start..=y_end {
-                clay_tiles.insert((x, y));
-            }
+        for tile in self.tiles[left + 1..right].iter_mut() {
+            *tile = Tile::Settled;
         }
-    }
 
-    let map: Map = (clay_tiles, HashSet::new(), HashSet::new());
+        Some((left + 1, right))
+    }
 
-    (map, (top_left, bottom_right))
-}
+    /// Lets water pour out of all the springs until nothing changes anymore.
+    ///
+    /// Every tile on the stack holds flowing water which may have to spread further: down if
+    /// there's sand below and sideways if it's resting on something solid. Whenever a row settles,
+    /// the flowing water above it has something to rest on and is looked at again.
+    fn flow(&mut self, springs: &[Position]) {
+        let mut stack: Vec<usize> = Vec::new();
+        for &spring in springs {
+            let index = self.index(spring);
+            self.tiles[index] = Tile::Flowing;
+            stack.push(index);
+        }
 
-#[allow(dead_code)]
-fn render_map(map: &Map, bounding_box: &BoundingBox) -> String {
-    let mut render = String::new();
-
-    let top_left = bounding_box.0;
-    let bottom_right = bounding_box.1;
-
-    let (clay, still_water, flowing_water) = map;
-
-    for y in top_left.1..=bottom_right.1 {
-        for x in top_left.0..=bottom_right.0 {
-            let c;
-            let pos = &(x, y);
-            if clay.contains(pos) {
-                c = '#';
-            } else if still_water.contains(pos) && flowing_water.contains(pos) {
-                c = '$';
-            } else if still_water.contains(pos) {
-                c = '~';
-            } else if flowing_water.contains(pos) {
-                c = '|';
-            } else {
-                c = ' ';
+        while let Some(index) = stack.pop() {
+            if self.tiles[index] != Tile::Flowing {
+                continue;
             }
 
-            render.push(c);
-        }
-
-        render.push('\n');
-    }
+            let below = match self.below(index) {
+                Some(below) => below,
+                None => continue
+            };
 
-    render
-}
+            match self.tiles[below] {
+                Tile::Sand => {
+                    self.tiles[below] = Tile::Flowing;
+                    stack.push(below);
+                    continue;
+                }
+                Tile::Flowing => continue,
+                _ => {}
+            }
 
-fn fall(map: &mut Map, mut pos: Position, max_y: usize) -> Option<Position> {
-    let (clay, _, flowing_water) = map;
+            let x = index % self.width;
+            let sideways = [
+                if x > 0 { Some(index - 1) } else { None },
+                if x + 1 < self.width { Some(index + 1) } else { None },
+            ];
+            for &next in sideways.iter().filter_map(|next| next.as_ref()) {
+                if self.tiles[next] == Tile::Sand {
+                    self.tiles[next] = Tile::Flowing;
+                    stack.push(next);
+                }
+            }
 
-    while pos.1 <= max_y {
-        let pos_down = (pos.0, pos.1 + 1);
-        if !clay.contains(&pos_down) {
-            flowing_water.insert(pos);
-            pos = pos_down;
-        } else {
-            return Some(pos);
+            if let Some((start, end)) = self.try_settle(index) {
+                if index >= self.width {
+                    stack.extend((start..end).map(|i| i - self.width));
+                }
+            }
         }
     }
 
-    None
-}
+    /// Settled and flowing water tiles within the given bounds, inclusive.
+    fn count_water(&self, bounds: BoundingBox) -> WaterCount {
+        let ((min_x, min_y), (max_x, max_y)) = bounds;
+        let mut count = WaterCount::default();
+
+        for (index, &tile) in self.tiles.iter().enumerate() {
+            let (x, y) = self.position(index);
+            if x < min_x || x > max_x || y < min_y || y > max_y {
+                continue;
+            }
 
-fn spread_dir(map: &Map, temp: &mut HashSet<Position>, pos: Position, direction: isize) -> Option<Position> {
-    let (clay, still_water, _) = map;
+            match tile {
+                Tile::Settled => count.settled += 1,
+                Tile::Flowing => count.flowing += 1,
+                _ => {}
+            }
+        }
 
-    let mut pos1 = pos;
+        count
+    }
 
-    while !clay.contains(&pos1) {
-        temp.insert(pos1);
-        let pos2 = (pos1.0, pos1.1 + 1);
-        if !(clay.contains(&pos2) || still_water.contains(&pos2)) {
-            return Some(pos1);
+    fn render(&self) -> String {
+        let mut render = String::new();
+
+        for row in self.tiles.chunks(self.width) {
+            render.extend(row.iter().map(|tile| match tile {
+                Tile::Sand => '.',
+                Tile::Clay => '#',
+                Tile::Flowing => '|',
+                Tile::Settled => '~',
+            }));
+            render.push('\n');
         }
 
-        pos1 = ((pos1.0 as isize + direction) as usize, pos1.1);
+        render
     }
+}
 
-    None
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct WaterCount {
+    settled: usize,
+    flowing: usize,
 }
 
-fn spread(map: &mut Map, pos: Position) -> (Option<Position>, Option<Position>) {
-    let mut temp: HashSet<Position> = HashSet::new();
+impl WaterCount {
+    fn total(&self) -> usize {
+        self.settled + self.flowing
+    }
+}
 
-    let pl = spread_dir(map, &mut temp, pos, -1);
-    let pr = spread_dir(map, &mut temp, pos, 1);
+fn get_clay<T: BufRead>(input: T) -> Result<(Vec<Position>, BoundingBox), String> {
+    let x_first_parser = Regex::new(r#"(?:x=(?P<x_start>\d+), y=(?P<y_start>\d+)(?:\.{2}(?P<y_end>\d+))?)"#).unwrap();
+    let y_first_parser = Regex::new(r#"(?:y=(?P<y_start>\d+), x=(?P<x_start>\d+)(?:\.{2}(?P<x_end>\d+))?)"#).unwrap();
 
-    let (_, still_water, flowing_water) = map;
-    if pl.is_none() && pr.is_none() {
-        still_water.extend(temp.iter());
-    } else {
-        flowing_water.extend(temp.iter());
-    }
+    let mut clay_tiles = Vec::new();
+    let mut top_left = (usize::MAX, usize::MAX);
+    let mut bottom_right = (usize::MIN, usize::MIN);
 
-    (pl, pr)
-}
+    let lines = utils::get_lines(input);
+    for line in lines.iter() {
+        let captures = x_first_parser.captures(line)
+            .or_else(|| y_first_parser.captures(line))
+            .ok_or_else(|| format!("line `{}` didn't match the format", line))?;
 
-fn flow(map: &mut Map, pos: Position, max_y: usize) {
-    let mut to_fall = HashSet::new();
-    to_fall.insert(pos);
+        let number = |name: &str| captures.name(name).map(|m| m.as_str().parse::<usize>().unwrap());
+        let x_start = number("x_start").unwrap();
+        let x_end = number("x_end").unwrap_or(x_start);
+        let y_start = number("y_start").unwrap();
+        let y_end = number("y_end").unwrap_or(y_start);
 
-    let mut to_spread = HashSet::new();
+        top_left = (top_left.0.min(x_start), top_left.1.min(y_start));
+        bottom_right = (bottom_right.0.max(x_end), bottom_right.1.max(y_end));
 
-    while !(to_fall.is_empty() && to_spread.is_empty()) {
-        while let Some(pos) = hashset_take_arbitrary(&mut to_fall) {
-            if let Some(origin) = fall(map, pos, max_y) {
-                to_spread.insert(origin);
+        for x in x_start..=x_end {
+            for y in y_start..=y_end {
+                clay_tiles.push((x, y));
             }
         }
+    }
 
-        while let Some(origin) = hashset_take_arbitrary(&mut to_spread) {
-            let (pl, pr) = spread(map, origin);
+    if clay_tiles.is_empty() {
+        return Err("There's no clay".to_string());
+    }
 
-            if pl.is_none() && pr.is_none() {
-                to_spread.insert((origin.0, origin.1 - 1));
-            } else {
-                if let Some(pos) = pl {
-                    to_fall.insert(pos);
-                }
-                if let Some(pos) = pr {
-                    to_fall.insert(pos);
-                }
-            }
-        }
+    Ok((clay_tiles, (top_left, bottom_right)))
+}
+
+fn parse_springs(args: &[&str]) -> Result<Vec<Position>, String> {
+    if args.is_empty() {
+        return Ok(vec![SPRING]);
     }
+
+    args.iter()
+        .map(|arg| {
+            let coordinates: Vec<usize> = arg.split(',')
+                .map(|coordinate| coordinate.trim().parse())
+                .collect::<Result<_, _>>()
+                .map_err(|_| format!("invalid spring `{}`, expected `x,y`", arg))?;
+
+            match coordinates[..] {
+                [x, y] => Ok((x, y)),
+                _ => Err(format!("invalid spring `{}`, expected `x,y`", arg))
+            }
+        })
+        .collect()
 }
 
-pub fn solve_first<T: BufRead>(input: T) -> u32 {
-    let (mut map, bounding_box) = get_map(input);
-    let min_y = (bounding_box.0).1;
+/// Fills the reservoirs from the springs. Water below the lowest clay flows away forever, so
+/// the map ends there.
+fn simulate(clay: &[Position], bounding_box: BoundingBox, springs: &[Position]) -> Result<Map, String> {
     let max_y = (bounding_box.1).1;
+    if let Some(spring) = springs.iter().find(|spring| spring.1 > max_y || clay.contains(spring)) {
+        return Err(format!("the spring at {},{} is inside clay or below all of it", spring.0, spring.1));
+    }
 
-    flow(&mut map, (500, 0), max_y);
+    let mut map = Map::new(clay, springs);
+    map.flow(springs);
+    Ok(map)
+}
 
-    map.1.union(&map.2)
-        .filter(|&pos| min_y <= pos.1 && max_y >= pos.1)
-        .count() as u32
+/// The puzzle only counts water from the highest to the lowest clay, whatever the x coordinate.
+fn puzzle_bounds(bounding_box: BoundingBox) -> BoundingBox {
+    ((usize::MIN, (bounding_box.0).1), (usize::MAX, (bounding_box.1).1))
 }
 
-pub fn solve_second<T: BufRead>(input: T) -> u32 {
-    let (mut map, bounding_box) = get_map(input);
-    let min_y = (bounding_box.0).1;
-    let max_y = (bounding_box.1).1;
+pub fn solve_first<T: BufRead>(input: T) -> Result<usize, String> {
+    let (clay, bounding_box) = get_clay(input)?;
+    let map = simulate(&clay, bounding_box, &[SPRING])?;
+
+    Ok(map.count_water(puzzle_bounds(bounding_box)).total())
+}
 
-    flow(&mut map, (500, 0), max_y);
+pub fn solve_second<T: BufRead>(input: T) -> Result<usize, String> {
+    let (clay, bounding_box) = get_clay(input)?;
+    let map = simulate(&clay, bounding_box, &[SPRING])?;
 
-    map.1.iter()
-        .filter(|&pos| min_y <= pos.1 && max_y >= pos.1)
-        .count() as u32
+    Ok(map.count_water(puzzle_bounds(bounding_box)).settled)
 }
 
 pub fn solve<T: BufRead>(problem: u8, input: T) -> Result<String, String> {
     match problem {
-        1 => Result::Ok(solve_first(input).to_string()),
-        2 => Result::Ok(solve_second(input).to_string()),
+        1 => solve_first(input).map(|result| result.to_string()),
+        2 => solve_second(input).map(|result| result.to_string()),
         _ => Result::Err("This problem only has 2 parts!".to_string())
     }
-}
\ No newline at end of file
+}
+
+/// `water [x,y...]` counts the water from the given springs (500,0 by default), `render [x,y...]`
+/// draws the map after the water has settled.
+pub fn run_mode<T: BufRead>(mode: &str, args: &[&str], input: T) -> Result<String, String> {
+    let springs = parse_springs(args)?;
+    let (clay, bounding_box) = get_clay(input)?;
+    let map = simulate(&clay, bounding_box, &springs)?;
+
+    match mode {
+        "water" => {
+            let everywhere = ((usize::MIN, usize::MIN), (usize::MAX, usize::MAX));
+            let lines: Vec<String> = [
+                ("puzzle rows", puzzle_bounds(bounding_box)),
+                ("clay bounding box", bounding_box),
+                ("overall", everywhere),
+            ].iter()
+                .map(|&(name, bounds)| {
+                    let count = map.count_water(bounds);
+                    format!("{:<18} {:>6} settled {:>6} flowing {:>6} total", name, count.settled, count.flowing, count.total())
+                })
+                .collect();
+
+            Ok(lines.join("\n"))
+        }
+        "render" => Ok(map.render()),
+        _ => Result::Err(format!("Unknown mode `{}`, expected water or render", mode))
+    }
+}
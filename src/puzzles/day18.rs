@@ -1,175 +1,99 @@
-use std::collections::HashSet;
 use std::io::BufRead;
 
+use automaton::{Alphabet, Automaton, Boundary, Grid, History, Neighbourhood, State};
+use automaton::rules::CountedRules;
 use utils;
 
-type Position = (usize, usize);
-type Grid = Vec<Vec<Tile>>;
+const TREE: State = 1;
+const LUMBERYARD: State = 2;
 
-#[derive(Clone, Eq, Hash, PartialEq)]
-enum Tile {
-    Open,
-    Tree,
-    Lumberyard,
-}
-
-#[allow(dead_code)]
-fn render_grid(grid: &Grid) -> String {
-    let mut render = String::new();
-
-    for row in grid.iter() {
-        for tile in row.iter() {
-            let c = match tile {
-                Tile::Open => ' ',
-                Tile::Tree => '|',
-                Tile::Lumberyard => '#',
-            };
+/// Minutes to simulate before giving up on the area ever repeating.
+const MAX_MINUTES: u64 = 100_000;
 
-            render.push(c);
-        }
-
-        render.push('\n');
-    }
+const RULES: &str = "
+    . -> | if | >= 3
+    | -> # if # >= 3
+    # -> # if # >= 1 and | >= 1
+    # -> .
+";
 
-    render
+fn alphabet() -> Alphabet {
+    Alphabet::new(".|#")
 }
 
-fn get_grid(lines: Vec<String>) -> Grid {
-    let mut grid = Grid::new();
+/// The lumber collection area, minute by minute. Every minute seen so far is remembered by its
+/// hash, so once the area repeats any later minute can be looked up without simulating it.
+struct Area {
+    history: History<CountedRules>,
+}
 
-    for line in lines.iter() {
-        let mut row = Vec::new();
+impl Area {
+    fn parse<T: BufRead>(input: T) -> Result<Area, String> {
+        let alphabet = alphabet();
+        let lines = utils::get_lines(input);
+        let lines: Vec<&str> = lines.iter().map(|line| line.as_str()).collect();
 
-        for c in line.chars() {
-            let tile = match c {
-                '.' => Tile::Open,
-                '|' => Tile::Tree,
-                '#' => Tile::Lumberyard,
-                _ => panic!("wat"),
-            };
+        let grid = Grid::parse(&lines, &alphabet)?;
+        let rules = CountedRules::parse(RULES, &alphabet)?;
+        let automaton = Automaton::new(grid, Neighbourhood::moore(1), rules, Boundary::Fixed)?;
 
-            row.push(tile);
-        }
+        Ok(Area { history: History::new(automaton) })
+    }
 
-        grid.push(row);
+    fn at(&mut self, minute: u64) -> Result<&Grid, String> {
+        self.history.at(minute, MAX_MINUTES).map(|snapshot| snapshot.grid)
     }
 
-    grid
+    /// Wooded acres times lumberyards after the given number of minutes.
+    fn resource_value(&mut self, minute: u64) -> Result<usize, String> {
+        let grid = self.at(minute)?;
+        Ok(grid.count(TREE) * grid.count(LUMBERYARD))
+    }
 }
 
-fn count_neighbours(grid: &Grid, pos: Position) -> (u8, u8, u8) {
-    let height = grid.len() as isize;
-    let width = grid[0].len() as isize;
-
-    let mut opens = 0;
-    let mut trees = 0;
-    let mut lumberyards = 0;
+fn parse_minute(arg: &str) -> Result<u64, String> {
+    arg.parse().map_err(|_| format!("invalid minute `{}`", arg))
+}
 
-    for i in -1..=1 {
-        for j in -1..=1 {
-            if i == 0 && j == 0 {
-                continue;
-            }
+pub fn solve_first<T: BufRead>(input: T) -> Result<usize, String> {
+    Area::parse(input)?.resource_value(10)
+}
 
-            let x = pos.0 as isize + i;
-            let y = pos.1 as isize + j;
+pub fn solve_second<T: BufRead>(input: T) -> Result<usize, String> {
+    Area::parse(input)?.resource_value(1_000_000_000)
+}
 
-            if (x >= 0 && x < width) && (y >= 0 && y < height) {
-                match grid[y as usize][x as usize] {
-                    Tile::Open => opens += 1,
-                    Tile::Tree => trees += 1,
-                    Tile::Lumberyard => lumberyards += 1,
-                };
-            }
-        }
+pub fn solve<T: BufRead>(problem: u8, input: T) -> Result<String, String> {
+    match problem {
+        1 => solve_first(input).map(|result| result.to_string()),
+        2 => solve_second(input).map(|result| result.to_string()),
+        _ => Result::Err("This problem only has 2 parts!".to_string())
     }
-
-    (opens, trees, lumberyards)
 }
 
-fn simulate(mut grid: Grid, minutes: usize) -> Grid {
-    let mut state_set: HashSet<Grid> = HashSet::new();
-    let mut states: Vec<Grid> = Vec::new();
-
-    let mut repeat_index = 0;
-
-    for _ in 0..minutes {
-        let mut next_grid = Grid::new();
-
-        for (y, row) in grid.iter().enumerate() {
-            let mut next_row = Vec::new();
+/// `value <minutes...>` prints the resource value after each of the given minutes,
+/// `render <minute>` draws the area after that many minutes.
+pub fn run_mode<T: BufRead>(mode: &str, args: &[&str], input: T) -> Result<String, String> {
+    let mut area = Area::parse(input)?;
 
-            for (x, tile) in row.iter().enumerate() {
-                let (_, tree_ns, lumberyard_ns) = count_neighbours(&grid, (x, y));
-
-                let next_tile = match tile {
-                    Tile::Open => if tree_ns >= 3 { Tile::Tree } else { Tile::Open },
-                    Tile::Tree => if lumberyard_ns >= 3 { Tile::Lumberyard } else { Tile::Tree },
-                    Tile::Lumberyard => if lumberyard_ns >= 1 && tree_ns >= 1 { Tile::Lumberyard } else { Tile::Open },
-                };
-
-                next_row.push(next_tile)
+    match mode {
+        "value" => {
+            if args.is_empty() {
+                return Err("expected at least one minute".to_string());
             }
 
-            next_grid.push(next_row);
-        }
+            let mut lines = Vec::new();
+            for arg in args {
+                let minute = parse_minute(arg)?;
+                lines.push(format!("{}: {}", minute, area.resource_value(minute)?));
+            }
 
-        if state_set.contains(&next_grid) {
-            repeat_index = states.iter()
-                .position(|g| g == &next_grid)
-                .unwrap();
-            break;
+            Ok(lines.join("\n"))
         }
-
-        states.push(next_grid.clone());
-        state_set.insert(next_grid.clone());
-        grid = next_grid;
-    }
-
-    let total_states = states.len();
-    let cycle_len = total_states - repeat_index;
-    let minutes_left = minutes - repeat_index;
-
-    // this index calculation seriously ducked me up man...
-    let index = repeat_index + ((minutes_left - 1) % cycle_len);
-    let grid = states.remove(index);
-
-    grid
-}
-
-fn calc_resource_value(grid: &Grid) -> u32 {
-    let mut trees = 0;
-    let mut lumberyards = 0;
-
-    for tile in grid.iter().flatten() {
-        match tile {
-            Tile::Tree => trees += 1,
-            Tile::Lumberyard => lumberyards += 1,
-            _ => ()
-        };
+        "render" => match args {
+            [minute] => Ok(area.at(parse_minute(minute)?)?.render(&alphabet())),
+            _ => Err("expected the minute to render".to_string())
+        },
+        _ => Result::Err(format!("Unknown mode `{}`, expected value or render", mode))
     }
-
-
-    trees * lumberyards
-}
-
-pub fn solve_first<T: BufRead>(input: T) -> u32 {
-    let grid = get_grid(utils::get_lines(input));
-    let grid = simulate(grid, 10);
-    calc_resource_value(&grid)
-}
-
-
-pub fn solve_second<T: BufRead>(input: T) -> u32 {
-    let grid = get_grid(utils::get_lines(input));
-    let grid = simulate(grid, 1000000000);
-    calc_resource_value(&grid)
 }
-
-pub fn solve<T: BufRead>(problem: u8, input: T) -> Result<String, String> {
-    match problem {
-        1 => Result::Ok(solve_first(input).to_string()),
-        2 => Result::Ok(solve_second(input).to_string()),
-        _ => Result::Err("This problem only has 2 parts!".to_string())
-    }
-}
\ No newline at end of file
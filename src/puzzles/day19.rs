@@ -1,102 +1,36 @@
-use std::collections::HashMap;
 use std::io::BufRead;
 
+use elfcode::assembler;
+use elfcode::cfg::ControlFlowGraph;
+use elfcode::compiler::CompiledProgram;
+use elfcode::differential;
+use elfcode::profiler;
+use elfcode::{Arithmetic, Instruction, Opcode, Overflow, Program, Stop, Width, REGISTER_COUNT};
 use utils;
 
 type RegisterType = usize;
 type Registry = Vec<RegisterType>;
-type Operation = (String, RegisterType, RegisterType, RegisterType);
-type OpMap = HashMap<String, Op>;
-
-macro_rules! hashmap {
-    ($( $key: expr => $val: expr ),*) => {{
-         let mut map = ::std::collections::HashMap::new();
-         $( map.insert($key, $val); )*
-         map
-    }}
-}
-
-struct Op {
-    op: Box<Fn(&Registry, RegisterType, RegisterType) -> RegisterType>
-}
-
-impl Op {
-    fn new(op: Box<Fn(&Registry, RegisterType, RegisterType) -> RegisterType>) -> Op {
-        Op { op }
-    }
-    fn perform(&self, registry: &mut Registry, a: RegisterType, b: RegisterType, c: usize) {
-        registry[c] = (self.op)(registry, a, b);
-    }
-}
-
-fn get_opcodes() -> OpMap {
-    hashmap![
-        "addr".to_string() => Op::new(Box::new(|register: &Registry, a, b| register[a as usize] + register[b as usize])),
-        "addi".to_string() => Op::new(Box::new(|register: &Registry, a, b| register[a as usize] + b)),
-        "mulr".to_string() => Op::new(Box::new(|register: &Registry, a, b| register[a as usize] * register[b as usize])),
-        "muli".to_string() => Op::new(Box::new(|register: &Registry, a, b| register[a as usize] * b)),
-        "banr".to_string() => Op::new(Box::new(|register: &Registry, a, b| register[a as usize] & register[b as usize])),
-        "bani".to_string() => Op::new(Box::new(|register: &Registry, a, b| register[a as usize] & b)),
-        "borr".to_string() => Op::new(Box::new(|register: &Registry, a, b| register[a as usize] | register[b as usize])),
-        "bori".to_string() => Op::new(Box::new(|register: &Registry, a, b| register[a as usize] | b)),
-        "setr".to_string() => Op::new(Box::new(|register: &Registry, a, _| register[a as usize])),
-        "seti".to_string() => Op::new(Box::new(|_, a, _| a)),
-        "gtir".to_string() => Op::new(Box::new(|register: &Registry, a, b| (a > register[b as usize]) as RegisterType)),
-        "gtri".to_string() => Op::new(Box::new(|register: &Registry, a, b| (register[a as usize] > b) as RegisterType)),
-        "gtrr".to_string() => Op::new(Box::new(|register: &Registry, a, b| (register[a as usize] > register[b as usize]) as RegisterType)),
-        "eqir".to_string() => Op::new(Box::new(|register: &Registry, a, b| (a == register[b as usize]) as RegisterType)),
-        "eqri".to_string() => Op::new(Box::new(|register: &Registry, a, b| (register[a as usize] == b) as RegisterType)),
-        "eqrr".to_string() => Op::new(Box::new(|register: &Registry, a, b| (register[a as usize] == register[b as usize]) as RegisterType))
-    ]
-}
-
-
-fn parse_instruction(instruction: &str) -> Operation {
-    let mut it = instruction.split_whitespace();
-
-    let parse_next = |it: &mut Iterator<Item=&str>| it.next().unwrap().parse().unwrap();
-
-    (it.next().unwrap().to_string(), parse_next(&mut it), parse_next(&mut it), parse_next(&mut it))
-}
-
-fn get_input<T: BufRead>(input: T) -> Option<(usize, Vec<Operation>)> {
-    let mut lines = utils::get_lines(input);
-
-    let ip_line = lines.remove(0);
-    let ip = ip_line.split_whitespace()
-        .last()?
-        .parse().ok()?;
-
-    let mut program = Vec::new();
-
-    for line in lines.iter() {
-        program.push(parse_instruction(line.as_str()));
-    }
 
-    Some((ip, program))
+fn get_input<T: BufRead>(input: T) -> Result<Program, String> {
+    Program::parse(&utils::get_lines(input))
 }
 
 
-fn run_process(registry: &mut Registry, ip: usize, program: &Vec<Operation>) {
-    let opcodes = get_opcodes();
+fn run_process(registry: Registry, program: &Program) -> Result<Registry, String> {
+    let mut machine = CompiledProgram::new(program, registry);
 
-    while let Some(operation) = program.get(registry[ip]) {
-        let (opcode, a, b, c) = operation;
-        let op = &opcodes[opcode];
-        op.perform(registry, *a, *b, *c);
-
-        registry[ip] += 1;
+    match machine.run(None) {
+        Stop::Fault(fault) => Err(fault.to_string()),
+        _ => Ok(machine.registers)
     }
 }
 
 
-pub fn solve_first<T: BufRead>(input: T) -> RegisterType {
-    let (ip, program) = get_input(input)
-        .expect("Invalid input?");
+pub fn solve_first<T: BufRead>(input: T) -> Result<RegisterType, String> {
+    let program = get_input(input)?;
 
-    let mut registry: Registry = vec![0; 6];
-    run_process(&mut registry, ip, &program);
-    registry[0]
+    let registry = run_process(vec![0; REGISTER_COUNT], &program)?;
+    Ok(registry[0])
 }
 
 fn divisor_sum(num: u64) -> u64 {
@@ -115,33 +49,102 @@ fn divisor_sum(num: u64) -> u64 {
     result + 1 + num
 }
 
-pub fn solve_second<T: BufRead>(input: T) -> u64 {
-    let (ip, mut program) = get_input(input)
-        .expect("Invalid input?");
+pub fn solve_second<T: BufRead>(input: T) -> Result<u64, String> {
+    let mut program = get_input(input)?;
 
-    let mut registry: Registry = vec![0; 6];
+    let mut registry: Registry = vec![0; REGISTER_COUNT];
     registry[0] = 1;
 
     // Manipulate the program such that the last line doesn't enter the loop,
     // but instead returns immediately so we may retrieve the value for the c register!
-    let program_len = program.len();
-    *program.last_mut().unwrap() = ("seti".to_string(), program_len + 1, 0, ip);
+    let program_len = program.instructions.len();
+    let ip = program.ip;
+    *program.instructions.last_mut().unwrap() = Instruction::new(Opcode::Seti, program_len + 1, 0, ip);
 
-    run_process(&mut registry, ip, &program);
+    let registry = run_process(registry, &program)?;
 
     // No idea if every input actually does it like this, but this is all I can do right now.
     // See the reverse-engineering process at the bottom of this file!
-    divisor_sum(registry[2] as u64)
+    Ok(divisor_sum(registry[2] as u64))
 }
 
 pub fn solve<T: BufRead>(problem: u8, input: T) -> Result<String, String> {
     match problem {
-        1 => Result::Ok(solve_first(input).to_string()),
-        2 => Result::Ok(solve_second(input).to_string()),
+        1 => solve_first(input).map(|result| result.to_string()),
+        2 => solve_second(input).map(|result| result.to_string()),
         _ => Result::Err("This problem only has 2 parts!".to_string())
     }
 }
 
+fn assemble_source(source: &str) -> Result<Program, String> {
+    assembler::assemble(source).map_err(|errors| {
+        errors.iter()
+            .map(|e| e.to_string())
+            .collect::<Vec<String>>()
+            .join("\n")
+    })
+}
+
+/// `assemble` turns assembler source into a numeric program, `disassemble` goes the other way
+/// and `roundtrip` checks that disassembling and reassembling a program yields the same program.
+/// `differential [count] [seed]` compares the compiled engine with the interpreter on random programs
+/// and `profile [listing|json] [max steps] [register 0]` shows where the program spends its time.
+/// `cfg` prints the control-flow graph in Graphviz DOT format and `run [width] [wrap|saturate|trap] [register 0]`
+/// runs the program with registers of the given width and overflow policy.
+pub fn run_mode<T: BufRead>(mode: &str, args: &[&str], input: T) -> Result<String, String> {
+    match mode {
+        "assemble" => {
+            let source = utils::get_lines_until_exit(input).join("\n");
+            assemble_source(&source).map(|program| program.to_string())
+        }
+        "disassemble" => {
+            let program = get_input(input)?;
+            Ok(assembler::disassemble(&program))
+        }
+        "roundtrip" => {
+            let program = get_input(input)?;
+            let source = assembler::disassemble(&program);
+            let reassembled = assemble_source(&source)?;
+
+            if reassembled == program {
+                Ok(format!("{} instructions survived the round trip", program.instructions.len()))
+            } else {
+                Err(format!("round trip changed the program:\n{}", reassembled))
+            }
+        }
+        "differential" => {
+            let count = args.first().map_or(Ok(1000), |count| count.parse()).map_err(|_| "invalid count")?;
+            let seed = args.get(1).map_or(Ok(2018), |seed| seed.parse()).map_err(|_| "invalid seed")?;
+            differential::run(count, seed)
+        }
+        "profile" => profiler::run(&get_input(input)?, args),
+        "run" => {
+            let width = Width::parse(args.first().unwrap_or(&"64"))?;
+            let overflow = Overflow::parse(args.get(1).unwrap_or(&"wrap"))?;
+
+            let mut registry: Registry = vec![0; REGISTER_COUNT];
+            if let Some(value) = args.get(2) {
+                registry[0] = value.parse().map_err(|_| format!("invalid register value `{}`", value))?;
+            }
+
+            let program = get_input(input)?;
+            let mut machine = CompiledProgram::with_arithmetic(&program, registry, Arithmetic::new(width, overflow));
+            let stop = machine.run(None);
+
+            let mut out = format!("{:?} after {} instructions", machine.registers, machine.steps);
+            if let Stop::Fault(fault) = stop {
+                out = format!("{}\n{}", out, fault);
+            }
+            Ok(out)
+        }
+        "cfg" => {
+            let program = get_input(input)?;
+            Ok(ControlFlowGraph::build(&program).to_dot(&program))
+        }
+        _ => Result::Err(format!("Unknown mode `{}`, expected assemble, disassemble, roundtrip, differential, profile, cfg or run", mode))
+    }
+}
+
 // raw input but substituted with arithmetic operators and letters:
 /*
 d += 16
@@ -1,8 +1,10 @@
 extern crate petgraph;
 
-use std::cmp::Ordering::Equal;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
 use std::collections::HashSet;
 use std::collections::VecDeque;
+use std::fmt;
 use std::io::BufRead;
 
 use utils;
@@ -11,96 +13,502 @@ use self::petgraph::graphmap::UnGraphMap;
 
 type AxisValue = i32;
 type Position = (AxisValue, AxisValue);
+type Maze = UnGraphMap<Position, ()>;
 
+/// Doors a room has to be at least this far away to count for the second part.
+const FAR_AWAY: u32 = 1000;
 
-fn get_instructions<T: BufRead>(input: T) -> String {
-    let mut lines = utils::get_lines(input);
-    let line = lines.remove(0);
 
-    let len = line.len();
-    line[1..len - 1].to_string()
+/// One part of the route regex.
+#[derive(Clone, Debug, PartialEq)]
+enum Element {
+    /// Doors to go through one after the other, e.g. `NEWS`.
+    Directions(String),
+    /// Any one of the options, the last of which may be empty like in `(NEWS|)`.
+    Branch(Vec<Route>),
 }
 
-fn build_graph(instructions: &String) -> UnGraphMap<Position, f32> {
-    let mut maze: UnGraphMap<Position, f32> = UnGraphMap::new();
+/// The parsed route regex, without the `^` and `$` around it.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Route(Vec<Element>);
 
-    let mut positions: HashSet<Position> = HashSet::new();
-    let mut start_positions: HashSet<Position> = HashSet::new();
-    let mut end_positions: HashSet<Position> = HashSet::new();
+impl Route {
+    /// Tree view with one line per run of directions and the options of branches indented.
+    fn pretty(&self) -> String {
+        let mut lines = Vec::new();
+        self.pretty_lines(0, &mut lines);
+        lines.join("\n")
+    }
 
-    positions.insert((0, 0));
-    start_positions.insert((0, 0));
+    fn pretty_lines(&self, depth: usize, lines: &mut Vec<String>) {
+        let indent = "  ".repeat(depth);
+        if self.0.is_empty() {
+            lines.push(format!("{}<empty>", indent));
+        }
 
-    let mut stack = VecDeque::new();
+        for element in self.0.iter() {
+            match element {
+                Element::Directions(directions) => lines.push(format!("{}{}", indent, directions)),
+                Element::Branch(options) => {
+                    lines.push(format!("{}(", indent));
+                    for (index, option) in options.iter().enumerate() {
+                        if index > 0 {
+                            lines.push(format!("{}|", indent));
+                        }
+                        option.pretty_lines(depth + 1, lines);
+                    }
+                    lines.push(format!("{})", indent));
+                }
+            }
+        }
+    }
+}
 
-    for instruction in instructions.chars() {
-        match instruction {
-            'N' | 'E' | 'S' | 'W' => {
-                let mut new_positions = HashSet::new();
+impl fmt::Display for Route {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for element in self.0.iter() {
+            match element {
+                Element::Directions(directions) => write!(f, "{}", directions)?,
+                Element::Branch(options) => {
+                    let options: Vec<String> = options.iter().map(|option| option.to_string()).collect();
+                    write!(f, "({})", options.join("|"))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Something wrong with the route regex, `position` counts characters from 0.
+#[derive(Clone, Debug, PartialEq)]
+struct RegexError {
+    position: usize,
+    message: String,
+}
+
+impl RegexError {
+    fn new(position: usize, message: &str) -> RegexError {
+        RegexError { position, message: message.to_string() }
+    }
 
-                for &position in positions.iter() {
-                    let next_position = match instruction {
-                        'N' => (position.0, position.1 + 1),
-                        'E' => (position.0 + 1, position.1),
-                        'S' => (position.0, position.1 - 1),
-                        'W' => (position.0 - 1, position.1),
-                        _ => unreachable!()
-                    };
+    /// The error together with the regex and a marker pointing at where it went wrong.
+    fn describe(&self, regex: &str) -> String {
+        format!("{}\n{}\n{}^", self, regex, " ".repeat(self.position))
+    }
+}
+
+impl fmt::Display for RegexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "character {}: {}", self.position + 1, self.message)
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).cloned()
+    }
+
+    /// Parses `^route$`, nothing may come after the `$`.
+    fn parse(regex: &str) -> Result<Route, RegexError> {
+        let mut parser = Parser { chars: regex.chars().collect(), position: 0 };
+        if parser.peek() != Some('^') {
+            return Err(RegexError::new(0, "the route has to start with `^`"));
+        }
+
+        parser.position += 1;
+        let route = parser.route()?;
+
+        match parser.peek() {
+            Some('$') => {}
+            Some(')') => return Err(RegexError::new(parser.position, "`)` without a `(` to close")),
+            Some('|') => return Err(RegexError::new(parser.position, "`|` outside of a branch")),
+            _ => return Err(RegexError::new(parser.position, "the route has to end with `$`"))
+        }
+
+        parser.position += 1;
+        if parser.position < parser.chars.len() {
+            return Err(RegexError::new(parser.position, "there's more after the `$`"));
+        }
 
-                    maze.add_edge(position, next_position, 1.0);
-                    new_positions.insert(next_position);
+        Ok(route)
+    }
+
+    /// Directions and branches up to the next `|`, `)` or `$`.
+    fn route(&mut self) -> Result<Route, RegexError> {
+        let mut elements = Vec::new();
+
+        loop {
+            match self.peek() {
+                Some(c @ 'N') | Some(c @ 'E') | Some(c @ 'S') | Some(c @ 'W') => {
+                    self.position += 1;
+                    match elements.last_mut() {
+                        Some(Element::Directions(directions)) => directions.push(c),
+                        _ => elements.push(Element::Directions(c.to_string()))
+                    }
                 }
+                Some('(') => elements.push(self.branch()?),
+                Some('|') | Some(')') | Some('$') | None => return Ok(Route(elements)),
+                Some(c) => return Err(RegexError::new(self.position, &format!("unexpected `{}`, expected a direction, `(`, `|`, `)` or `$`", c)))
+            }
+        }
+    }
 
-                positions = new_positions;
+    fn branch(&mut self) -> Result<Element, RegexError> {
+        let start = self.position;
+        self.position += 1;
+
+        let mut options = vec![self.route()?];
+        loop {
+            match self.peek() {
+                Some('|') => {
+                    self.position += 1;
+                    options.push(self.route()?);
+                }
+                Some(')') => {
+                    self.position += 1;
+                    return Ok(Element::Branch(options));
+                }
+                _ => return Err(RegexError::new(start, "`(` is never closed"))
             }
-            '(' => {
-                stack.push_back((start_positions.clone(), end_positions.clone()));
-                start_positions = positions.clone();
-                end_positions = HashSet::new();
+        }
+    }
+}
+
+fn get_route(lines: &[String]) -> Result<Route, String> {
+    let regex = lines.first().ok_or("there's no route regex")?.trim_end();
+    Parser::parse(regex).map_err(|e| e.describe(regex))
+}
+
+fn step(position: Position, direction: char) -> Position {
+    match direction {
+        'N' => (position.0, position.1 + 1),
+        'E' => (position.0 + 1, position.1),
+        'S' => (position.0, position.1 - 1),
+        'W' => (position.0 - 1, position.1),
+        _ => unreachable!("the parser only lets directions through")
+    }
+}
+
+/// Follows the route from all the given positions at once, adding the doors on the way to the
+/// maze. Returns every position the route can end up in.
+fn walk(route: &Route, mut positions: HashSet<Position>, maze: &mut Maze) -> HashSet<Position> {
+    for element in route.0.iter() {
+        positions = match element {
+            Element::Directions(directions) => {
+                for direction in directions.chars() {
+                    positions = positions.into_iter()
+                        .map(|position| {
+                            let next_position = step(position, direction);
+                            maze.add_edge(position, next_position, ());
+                            next_position
+                        })
+                        .collect();
+                }
+
+                positions
             }
-            ')' => {
-                positions.extend(end_positions.iter());
-                let (start, end) = stack.pop_back().unwrap();
-                start_positions = start;
-                end_positions = end;
+            Element::Branch(options) => options.iter()
+                .flat_map(|option| walk(option, positions.clone(), maze))
+                .collect()
+        };
+    }
+
+    positions
+}
+
+fn build_graph(route: &Route) -> Maze {
+    let mut maze = Maze::new();
+    maze.add_node((0, 0));
+
+    walk(route, [(0, 0)].iter().cloned().collect(), &mut maze);
+    maze
+}
+
+/// Draws the facility the way the puzzle does, with north at the top and `X` marking the room
+/// we're in.
+fn render_map(maze: &Maze) -> String {
+    let min_x = maze.nodes().map(|pos| pos.0).min().unwrap_or(0);
+    let max_x = maze.nodes().map(|pos| pos.0).max().unwrap_or(0);
+    let min_y = maze.nodes().map(|pos| pos.1).min().unwrap_or(0);
+    let max_y = maze.nodes().map(|pos| pos.1).max().unwrap_or(0);
+
+    let width = (max_x - min_x + 1) as usize * 2 + 1;
+    let height = (max_y - min_y + 1) as usize * 2 + 1;
+    let mut map = vec![vec!['#'; width]; height];
+
+    let cell = |pos: Position| ((pos.0 - min_x) as usize * 2 + 1, (max_y - pos.1) as usize * 2 + 1);
+    for room in maze.nodes() {
+        let (column, row) = cell(room);
+        map[row][column] = if room == (0, 0) { 'X' } else { '.' };
+    }
+
+    for (a, b, _) in maze.all_edges() {
+        let ((column_a, row_a), (column_b, row_b)) = (cell(a), cell(b));
+        let door = if row_a == row_b { '|' } else { '-' };
+        map[(row_a + row_b) / 2][(column_a + column_b) / 2] = door;
+    }
+
+    map.iter()
+        .map(|row| row.iter().collect::<String>())
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Reads a map drawn like [`render_map`] does back into the maze it shows.
+fn parse_map(lines: &[String]) -> Result<Maze, String> {
+    let map: Vec<Vec<char>> = lines.iter().map(|line| line.trim_end().chars().collect()).collect();
+    let at = |row: usize, column: usize| map.get(row).and_then(|line| line.get(column)).cloned().unwrap_or('#');
+
+    let mut start = None;
+    for (row, line) in map.iter().enumerate() {
+        for (column, &c) in line.iter().enumerate() {
+            match c {
+                'X' if start.is_some() => return Err(format!("{}:{}: there's more than one `X`", row + 1, column + 1)),
+                'X' => start = Some((row, column)),
+                '#' | '.' | '|' | '-' => {}
+                _ => return Err(format!("{}:{}: unexpected `{}` in the map", row + 1, column + 1, c))
+            }
+        }
+    }
+
+    let (start_row, start_column) = start.ok_or("the map doesn't mark the starting room with `X`")?;
+    let position = |row: usize, column: usize| (
+        (column as AxisValue - start_column as AxisValue) / 2,
+        (start_row as AxisValue - row as AxisValue) / 2,
+    );
+    let is_room = |row: usize, column: usize| ['.', 'X'].contains(&at(row, column))
+        && row % 2 == start_row % 2 && column % 2 == start_column % 2;
+
+    let mut maze = Maze::new();
+    for (row, line) in map.iter().enumerate() {
+        for (column, &c) in line.iter().enumerate() {
+            if is_room(row, column) {
+                maze.add_node(position(row, column));
+                continue;
             }
-            '|' => {
-                end_positions.extend(positions.iter());
-                positions = start_positions.clone();
+
+            let rooms = match c {
+                '|' if column > 0 => ((row, column - 1), (row, column + 1)),
+                '-' if row > 0 => ((row - 1, column), (row + 1, column)),
+                '.' | 'X' => return Err(format!("{}:{}: room isn't on the room grid", row + 1, column + 1)),
+                '#' => continue,
+                _ => return Err(format!("{}:{}: door on the edge of the map", row + 1, column + 1))
+            };
+
+            let ((row_a, column_a), (row_b, column_b)) = rooms;
+            if !is_room(row_a, column_a) || !is_room(row_b, column_b) {
+                return Err(format!("{}:{}: door doesn't connect two rooms", row + 1, column + 1));
             }
-            _ => panic!("what's this???")
+
+            maze.add_edge(position(row_a, column_a), position(row_b, column_b), ());
         }
     }
 
-    maze
+    Ok(maze)
+}
+
+/// Doors as pairs of rooms in a canonical order, so two mazes can be compared.
+fn doors(maze: &Maze) -> BTreeSet<(Position, Position)> {
+    maze.all_edges()
+        .map(|(a, b, _)| if a < b { (a, b) } else { (b, a) })
+        .collect()
 }
 
-pub fn solve_first<T: BufRead>(input: T) -> u32 {
-    let instructions = get_instructions(input);
-    let maze = build_graph(&instructions);
+/// Compares the maze the regex describes with the map that's expected of it.
+fn verify_map(maze: &Maze, expected: &Maze) -> Result<String, String> {
+    let (actual_doors, expected_doors) = (doors(maze), doors(expected));
+    let describe = |&(a, b): &(Position, Position)| format!("{},{} - {},{}", a.0, a.1, b.0, b.1);
+
+    let mut problems: Vec<String> = Vec::new();
+    problems.extend(actual_doors.difference(&expected_doors).map(|door| format!("unexpected door {}", describe(door))));
+    problems.extend(expected_doors.difference(&actual_doors).map(|door| format!("missing door {}", describe(door))));
+
+    let actual_rooms: BTreeSet<Position> = maze.nodes().collect();
+    let expected_rooms: BTreeSet<Position> = expected.nodes().collect();
+    problems.extend(actual_rooms.difference(&expected_rooms).map(|room| format!("unexpected room {},{}", room.0, room.1)));
+    problems.extend(expected_rooms.difference(&actual_rooms).map(|room| format!("missing room {},{}", room.0, room.1)));
+
+    if problems.is_empty() {
+        Ok(format!("the map matches ({} rooms, {} doors)", actual_rooms.len(), actual_doors.len()))
+    } else {
+        Err(format!("the map doesn't match:\n{}\n\nthe regex describes:\n{}", problems.join("\n"), render_map(maze)))
+    }
+}
+
+/// Shortest routes from the starting room to every other room.
+struct Routes {
+    distances: HashMap<Position, u32>,
+    /// The room every room is first reached from.
+    previous: HashMap<Position, Position>,
+}
+
+impl Routes {
+    /// Breadth-first search from the starting room, every door takes one step.
+    fn find(maze: &Maze) -> Routes {
+        let mut distances = HashMap::new();
+        let mut previous = HashMap::new();
+        let mut queue = VecDeque::new();
+
+        distances.insert((0, 0), 0);
+        queue.push_back((0, 0));
+
+        while let Some(room) = queue.pop_front() {
+            let distance = distances[&room];
+
+            for next in maze.neighbors(room) {
+                if distances.contains_key(&next) {
+                    continue;
+                }
+
+                distances.insert(next, distance + 1);
+                previous.insert(next, room);
+                queue.push_back(next);
+            }
+        }
+
+        Routes { distances, previous }
+    }
+
+    /// Least number of doors between the starting room and the given one, if it can be reached.
+    fn distance(&self, room: Position) -> Option<u32> {
+        self.distances.get(&room).cloned()
+    }
+
+    /// The room which takes the most doors to get to. If there are several, the first one in
+    /// reading order from the north.
+    fn furthest(&self) -> (Position, u32) {
+        self.distances.iter()
+            .map(|(&room, &distance)| (room, distance))
+            .max_by_key(|&((x, y), distance)| (distance, y, -x))
+            .unwrap()
+    }
+
+    /// Number of rooms that take at least `threshold` doors to get to.
+    fn at_least(&self, threshold: u32) -> usize {
+        self.distances.values().filter(|&&distance| distance >= threshold).count()
+    }
+
+    /// Directions to take from the starting room to get to the given one.
+    fn path_to(&self, room: Position) -> Option<String> {
+        self.distance(room)?;
 
-    let (path_costs, _) = petgraph::algo::bellman_ford(&maze, (0, 0)).unwrap();
+        let mut directions = Vec::new();
+        let mut current = room;
+        while let Some(&previous) = self.previous.get(&current) {
+            directions.push(match (current.0 - previous.0, current.1 - previous.1) {
+                (0, 1) => 'N',
+                (1, 0) => 'E',
+                (0, -1) => 'S',
+                (-1, 0) => 'W',
+                _ => unreachable!("rooms are only connected to their neighbours")
+            });
+            current = previous;
+        }
+
+        Some(directions.iter().rev().collect())
+    }
+}
 
-    *path_costs.iter()
-        .max_by(|a, b| a.partial_cmp(b).unwrap_or(Equal))
-        .unwrap() as u32
+fn parse_room(arg: &str) -> Result<Position, String> {
+    let coordinates: Vec<AxisValue> = arg.split(',')
+        .map(|coordinate| coordinate.trim().parse())
+        .collect::<Result<_, _>>()
+        .map_err(|_| format!("invalid room `{}`, expected `x,y` with north being positive y", arg))?;
+
+    match coordinates[..] {
+        [x, y] => Ok((x, y)),
+        _ => Err(format!("invalid room `{}`, expected `x,y` with north being positive y", arg))
+    }
 }
 
-pub fn solve_second<T: BufRead>(input: T) -> u32 {
-    let instructions = get_instructions(input);
-    let maze = build_graph(&instructions);
+fn get_routes<T: BufRead>(input: T) -> Result<Routes, String> {
+    let route = get_route(&utils::get_lines(input))?;
+    Ok(Routes::find(&build_graph(&route)))
+}
 
-    let (path_costs, _) = petgraph::algo::bellman_ford(&maze, (0, 0)).unwrap();
+pub fn solve_first<T: BufRead>(input: T) -> Result<u32, String> {
+    Ok(get_routes(input)?.furthest().1)
+}
 
-    path_costs.iter()
-        .filter(|&&a| a >= 1000.0)
-        .count() as u32
+pub fn solve_second<T: BufRead>(input: T) -> Result<usize, String> {
+    Ok(get_routes(input)?.at_least(FAR_AWAY))
 }
 
 pub fn solve<T: BufRead>(problem: u8, input: T) -> Result<String, String> {
     match problem {
-        1 => Result::Ok(solve_first(input).to_string()),
-        2 => Result::Ok(solve_second(input).to_string()),
+        1 => solve_first(input).map(|result| result.to_string()),
+        2 => solve_second(input).map(|result| result.to_string()),
         _ => Result::Err("This problem only has 2 parts!".to_string())
     }
-}
\ No newline at end of file
+}
+
+/// `ast` shows the parsed route regex as a tree and `regex` prints it again as it was understood.
+/// `render` draws the facility the regex describes, `verify` expects the regex to be followed by
+/// the map the puzzle draws for it and checks that they agree and `map` draws a map read from the
+/// input again, which shows how it was understood.
+/// `distance <x,y...>` tells how many doors it takes to get to the given rooms, `path [x,y]` the
+/// directions to the given room (or the furthest one) and `far [threshold]` counts the rooms
+/// which take at least that many doors (1000 by default) to get to.
+pub fn run_mode<T: BufRead>(mode: &str, args: &[&str], input: T) -> Result<String, String> {
+    let lines: Vec<String> = utils::get_lines_until_exit(input).into_iter()
+        .filter(|line| !line.trim().is_empty())
+        .collect();
+    if lines.is_empty() {
+        return Err("the input is empty".to_string());
+    }
+
+    let route = || get_route(&lines);
+    let maze = || route().map(|route| build_graph(&route));
+    let routes = || maze().map(|maze| Routes::find(&maze));
+
+    match mode {
+        "ast" => Ok(route()?.pretty()),
+        "regex" => Ok(format!("^{}$", route()?)),
+        "render" => Ok(render_map(&maze()?)),
+        "verify" => verify_map(&maze()?, &parse_map(&lines[1..])?),
+        "map" => Ok(render_map(&parse_map(&lines)?)),
+        "distance" => {
+            if args.is_empty() {
+                return Err("expected at least one room".to_string());
+            }
+
+            let routes = routes()?;
+            let mut lines = Vec::new();
+            for arg in args {
+                let room = parse_room(arg)?;
+                lines.push(match routes.distance(room) {
+                    Some(distance) => format!("{},{}: {}", room.0, room.1, distance),
+                    None => format!("{},{}: unreachable", room.0, room.1)
+                });
+            }
+
+            Ok(lines.join("\n"))
+        }
+        "path" => {
+            let routes = routes()?;
+            let room = match args {
+                [] => routes.furthest().0,
+                [room] => parse_room(room)?,
+                _ => return Err("expected at most one room".to_string())
+            };
+
+            routes.path_to(room).ok_or_else(|| format!("room {},{} can't be reached", room.0, room.1))
+        }
+        "far" => {
+            let threshold = match args {
+                [] => FAR_AWAY,
+                [threshold] => threshold.parse().map_err(|_| format!("invalid threshold `{}`", threshold))?,
+                _ => return Err("expected at most one threshold".to_string())
+            };
+
+            Ok(routes()?.at_least(threshold).to_string())
+        }
+        _ => Result::Err(format!("Unknown mode `{}`, expected ast, regex, render, verify, map, distance, path or far", mode))
+    }
+}
@@ -1,5 +1,5 @@
-extern crate petgraph;
-
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
 use std::collections::HashMap;
 use std::io::BufRead;
 
@@ -7,12 +7,12 @@ use regex::Regex;
 
 use utils;
 
-use self::petgraph::graphmap::UnGraphMap;
-
 type Coordinate = (usize, usize);
-type Map = HashMap<Coordinate, u32>;
+type State = (Coordinate, Tool);
 
 const EROSION_MOD: u32 = 20183;
+const MOVE_MINUTES: u32 = 1;
+const SWITCH_MINUTES: u32 = 7;
 
 fn parse_input<T: BufRead>(input: T) -> (u32, Coordinate) {
     let regex = Regex::new(r"depth: (?P<depth>\d+)\s*target: (?P<target_x>[\d]+),(?P<target_y>[\d]+)").unwrap();
@@ -35,112 +35,359 @@ fn parse_input<T: BufRead>(input: T) -> (u32, Coordinate) {
     (depth, (target_x, target_y))
 }
 
-pub fn solve_first<T: BufRead>(input: T) -> u32 {
-    let (depth, target) = parse_input(input);
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+enum Tool {
+    None,
+    Torch,
+    ClimbingGear,
+}
 
-    let mut erosion_levels: Map = HashMap::new();
-    let mut risk_level = 0;
-
-    for x in 0..=target.0 {
-        for y in 0..=target.1 {
-            let geologic_index: u32;
-
-            if (x, y) == target || (x, y) == (0, 0) {
-                geologic_index = 0;
-            } else if x == 0 {
-                geologic_index = (y * 48271) as u32;
-            } else if y == 0 {
-                geologic_index = (x * 16807) as u32;
-            } else {
-                geologic_index = erosion_levels.get(&(x - 1, y)).unwrap() * erosion_levels.get(&(x, y - 1)).unwrap();
-            }
+impl Tool {
+    fn name(self) -> &'static str {
+        match self {
+            Tool::None => "neither",
+            Tool::Torch => "torch",
+            Tool::ClimbingGear => "climbing gear",
+        }
+    }
 
-            let erosion_level = (geologic_index + depth) % EROSION_MOD;
-            risk_level += erosion_level % 3;
-            erosion_levels.insert((x, y), erosion_level);
+    /// How the route is drawn on the map, depending on the tool held.
+    fn symbol(self) -> char {
+        match self {
+            Tool::None => 'n',
+            Tool::Torch => 't',
+            Tool::ClimbingGear => 'c',
         }
     }
+}
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Action {
+    Move(Coordinate),
+    Switch(Tool),
+}
 
-    risk_level
+/// Something done on the way to the target and the minute it's done by.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Step {
+    minute: u32,
+    action: Action,
 }
 
-#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
-enum Tool {
-    None,
-    Torch,
-    ClimbingGear,
+#[derive(Clone, Debug, PartialEq)]
+struct Rescue {
+    minutes: u32,
+    steps: Vec<Step>,
 }
 
-pub fn solve_second<T: BufRead>(input: T) -> u32 {
-    let (depth, target) = parse_input(input);
+impl Rescue {
+    /// Every position on the route together with the tool held there, starting at the mouth.
+    fn positions(&self) -> Vec<State> {
+        let mut state = ((0, 0), Tool::Torch);
+        let mut positions = vec![state];
 
-    let mut erosion_levels: Map = HashMap::new();
-    let mut graph: UnGraphMap<(usize, usize, Tool), u32> = UnGraphMap::new();
+        for step in self.steps.iter() {
+            match step.action {
+                Action::Move(pos) => state.0 = pos,
+                Action::Switch(tool) => state.1 = tool,
+            }
+            positions.push(state);
+        }
 
-    graph.add_edge((0, 0, Tool::Torch), (0, 0, Tool::ClimbingGear), 7);
-    graph.add_node((target.0, target.1, Tool::Torch));
+        positions
+    }
+}
 
-    for x in 0..=target.0 + 500 {
-        for y in 0..=target.1 + 100 {
-            let geologic_index: u32;
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Region {
+    Rocky,
+    Wet,
+    Narrow,
+}
+
+impl Region {
+    fn risk_level(self) -> u32 {
+        match self {
+            Region::Rocky => 0,
+            Region::Wet => 1,
+            Region::Narrow => 2,
+        }
+    }
+
+    fn allows(self, tool: Tool) -> bool {
+        match self {
+            Region::Rocky => tool != Tool::None,
+            Region::Wet => tool != Tool::Torch,
+            Region::Narrow => tool != Tool::ClimbingGear,
+        }
+    }
+}
+
+/// The cave below the mouth, which goes on forever to the right and down. Erosion levels are only
+/// calculated once something asks for them and then remembered, the known part of the cave grows
+/// as needed.
+struct Cave {
+    depth: u32,
+    target: Coordinate,
+    /// Erosion levels by row, every row is equally long.
+    erosion: Vec<Vec<u32>>,
+}
+
+impl Cave {
+    fn new(depth: u32, target: Coordinate) -> Cave {
+        Cave { depth, target, erosion: Vec::new() }
+    }
 
-            if (x, y) == target || (x, y) == (0, 0) {
-                geologic_index = 0;
-            } else if x == 0 {
-                geologic_index = (y * 48271) as u32;
-            } else if y == 0 {
-                geologic_index = (x * 16807) as u32;
-            } else {
-                geologic_index = erosion_levels.get(&(x - 1, y)).unwrap() * erosion_levels.get(&(x, y - 1)).unwrap();
+    fn width(&self) -> usize {
+        self.erosion.first().map_or(0, |row| row.len())
+    }
+
+    /// Makes sure the erosion levels up to the given position are known. The cave grows at least
+    /// by half its size at a time so a search that keeps going a bit further doesn't have to
+    /// calculate a new column every single time.
+    fn explore(&mut self, pos: Coordinate) {
+        let (width, height) = (self.width(), self.erosion.len());
+        if pos.0 < width && pos.1 < height {
+            return;
+        }
+
+        let new_width = if pos.0 < width { width } else { (pos.0 + 1).max(width + width / 2) };
+        let new_height = if pos.1 < height { height } else { (pos.1 + 1).max(height + height / 2) };
+
+        for y in 0..new_height {
+            if y == self.erosion.len() {
+                self.erosion.push(Vec::with_capacity(new_width));
+            }
+
+            for x in self.erosion[y].len()..new_width {
+                let geologic_index = if (x, y) == self.target || (x, y) == (0, 0) {
+                    0
+                } else if y == 0 {
+                    (x as u64 * 16807 % EROSION_MOD as u64) as u32
+                } else if x == 0 {
+                    (y as u64 * 48271 % EROSION_MOD as u64) as u32
+                } else {
+                    self.erosion[y][x - 1] * self.erosion[y - 1][x]
+                };
+
+                let erosion_level = (geologic_index + self.depth) % EROSION_MOD;
+                self.erosion[y].push(erosion_level);
             }
+        }
+    }
 
-            let erosion_level = (geologic_index + depth) % EROSION_MOD;
-            erosion_levels.insert((x, y), erosion_level);
+    fn region(&mut self, pos: Coordinate) -> Region {
+        self.explore(pos);
+        match self.erosion[pos.1][pos.0] % 3 {
+            0 => Region::Rocky,
+            1 => Region::Wet,
+            _ => Region::Narrow,
+        }
+    }
 
-            let region_type = erosion_level % 3;
+    /// Total risk level of the rectangle between the given corners, inclusive.
+    fn risk_level(&mut self, top_left: Coordinate, bottom_right: Coordinate) -> u32 {
+        let mut risk_level = 0;
 
-            let tools = match region_type {
-                0 => vec![Tool::Torch, Tool::ClimbingGear],
-                1 => vec![Tool::None, Tool::ClimbingGear],
-                2 => vec![Tool::None, Tool::Torch],
-                _ => unreachable!(),
-            };
+        for y in top_left.1..=bottom_right.1 {
+            for x in top_left.0..=bottom_right.0 {
+                risk_level += self.region((x, y)).risk_level();
+            }
+        }
+
+        risk_level
+    }
 
-            let mut neighbours = Vec::new();
-            if x > 0 {
-                neighbours.push((x - 1, y));
+    /// Draws the regions from the mouth to the given corner the way the puzzle does, with the
+    /// route on top showing the tool held in each region.
+    fn render(&mut self, bottom_right: Coordinate, route: &Rescue) -> String {
+        let mut rows: Vec<Vec<char>> = (0..=bottom_right.1)
+            .map(|y| (0..=bottom_right.0)
+                .map(|x| match self.region((x, y)) {
+                    Region::Rocky => '.',
+                    Region::Wet => '=',
+                    Region::Narrow => '|',
+                })
+                .collect())
+            .collect();
+
+        for ((x, y), tool) in route.positions() {
+            if x <= bottom_right.0 && y <= bottom_right.1 {
+                rows[y][x] = tool.symbol();
             }
-            if y > 0 {
-                neighbours.push((x, y - 1));
+        }
+
+        let target = self.target;
+        rows[0][0] = 'M';
+        if target.0 <= bottom_right.0 && target.1 <= bottom_right.1 {
+            rows[target.1][target.0] = 'T';
+        }
+
+        rows.iter()
+            .map(|row| row.iter().collect::<String>())
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    /// Fastest way to get from the mouth to the target holding the torch.
+    ///
+    /// A* over positions together with the tool being held. The heuristic is the manhattan
+    /// distance to the target plus a switch if we're not holding the torch, which never
+    /// overestimates, so the first time the target is taken off the queue the route is optimal.
+    fn rescue(&mut self) -> Result<Rescue, String> {
+        let target = self.target;
+        // the mouth and the target share their region type, which only depends on the depth
+        if !self.region(target).allows(Tool::Torch) {
+            return Err(format!("with a depth of {} the target is in a wet region, which can't be entered with the torch", self.depth));
+        }
+
+        let estimate = |(x, y): Coordinate, tool: Tool| {
+            let distance = (x as i64 - target.0 as i64).abs() + (y as i64 - target.1 as i64).abs();
+            distance as u32 * MOVE_MINUTES + if tool == Tool::Torch { 0 } else { SWITCH_MINUTES }
+        };
+
+        let start = ((0, 0), Tool::Torch);
+        let mut minutes: HashMap<State, u32> = HashMap::new();
+        let mut previous: HashMap<State, State> = HashMap::new();
+        let mut queue = BinaryHeap::new();
+
+        minutes.insert(start, 0);
+        queue.push(Reverse((estimate(start.0, start.1), 0, start)));
+
+        while let Some(Reverse((_, elapsed, (pos, tool)))) = queue.pop() {
+            if (pos, tool) == (target, Tool::Torch) {
+                return Ok(Cave::reconstruct(&minutes, &previous, (pos, tool)));
+            }
+
+            if minutes[&(pos, tool)] < elapsed {
+                continue;
+            }
+
+            let region = self.region(pos);
+            let mut next_states = Vec::with_capacity(5);
+
+            for &other in [Tool::None, Tool::Torch, Tool::ClimbingGear].iter() {
+                if other != tool && region.allows(other) {
+                    next_states.push(((pos, other), elapsed + SWITCH_MINUTES));
+                }
             }
 
-            for &tool in tools.iter() {
-                let first_tool = *tools.first().unwrap();
-                if tool != first_tool {
-                    graph.add_edge((x, y, tool), (x, y, first_tool), 7);
+            let (x, y) = pos;
+            let neighbours = [
+                if x > 0 { Some((x - 1, y)) } else { None },
+                if y > 0 { Some((x, y - 1)) } else { None },
+                Some((x + 1, y)),
+                Some((x, y + 1)),
+            ];
+            for &next in neighbours.iter().filter_map(|next| next.as_ref()) {
+                if self.region(next).allows(tool) {
+                    next_states.push(((next, tool), elapsed + MOVE_MINUTES));
                 }
+            }
 
-                for &(n_x, n_y) in neighbours.iter() {
-                    if graph.contains_node((n_x, n_y, tool)) {
-                        graph.add_edge((n_x, n_y, tool), (x, y, tool), 1);
-                    }
+            for (state, next_elapsed) in next_states {
+                if minutes.get(&state).is_some_and(|&known| known <= next_elapsed) {
+                    continue;
                 }
+
+                minutes.insert(state, next_elapsed);
+                previous.insert(state, (pos, tool));
+                queue.push(Reverse((next_elapsed + estimate(state.0, state.1), next_elapsed, state)));
             }
         }
+
+        unreachable!("the cave is endless, so there's always a way to the target")
     }
 
+    /// Follows the states the search came from back from the target to the mouth.
+    fn reconstruct(minutes: &HashMap<State, u32>, previous: &HashMap<State, State>, end: State) -> Rescue {
+        let mut steps = Vec::new();
+        let mut state = end;
 
-    let (dist, _) = petgraph::algo::astar(&graph, (0, 0, Tool::Torch), |goal| goal == (target.0, target.1, Tool::Torch), |e| *e.2, |_| 1)
-        .expect("couldn't find path?");
+        while let Some(&before) = previous.get(&state) {
+            let action = if before.0 != state.0 { Action::Move(state.0) } else { Action::Switch(state.1) };
+            steps.push(Step { minute: minutes[&state], action });
+            state = before;
+        }
 
-    dist
+        steps.reverse();
+        Rescue { minutes: minutes[&end], steps }
+    }
+}
+
+fn parse_coordinate(arg: &str) -> Result<Coordinate, String> {
+    let coordinates: Vec<usize> = arg.split(',')
+        .map(|coordinate| coordinate.trim().parse())
+        .collect::<Result<_, _>>()
+        .map_err(|_| format!("invalid coordinate `{}`, expected `x,y`", arg))?;
+
+    match coordinates[..] {
+        [x, y] => Ok((x, y)),
+        _ => Err(format!("invalid coordinate `{}`, expected `x,y`", arg))
+    }
+}
+
+pub fn solve_first<T: BufRead>(input: T) -> u32 {
+    let (depth, target) = parse_input(input);
+    Cave::new(depth, target).risk_level((0, 0), target)
+}
+
+pub fn solve_second<T: BufRead>(input: T) -> Result<u32, String> {
+    let (depth, target) = parse_input(input);
+    Cave::new(depth, target).rescue().map(|rescue| rescue.minutes)
 }
 
 pub fn solve<T: BufRead>(problem: u8, input: T) -> Result<String, String> {
     match problem {
         1 => Result::Ok(solve_first(input).to_string()),
-        2 => Result::Ok(solve_second(input).to_string()),
+        2 => solve_second(input).map(|result| result.to_string()),
         _ => Result::Err("This problem only has 2 parts!".to_string())
     }
-}
\ No newline at end of file
+}
+
+/// `risk [x,y] [x,y]` adds up the risk level between two corners (the mouth and the target by
+/// default), `route` lists every move and tool switch of the fastest rescue with the minute it's
+/// done by and `map [x,y]` draws the cave up to the given corner with the route on top of it.
+pub fn run_mode<T: BufRead>(mode: &str, args: &[&str], input: T) -> Result<String, String> {
+    let (depth, target) = parse_input(input);
+    let mut cave = Cave::new(depth, target);
+
+    match mode {
+        "risk" => {
+            let corners = args.iter().map(|arg| parse_coordinate(arg)).collect::<Result<Vec<Coordinate>, String>>()?;
+            let (first, second) = match corners[..] {
+                [] => ((0, 0), target),
+                [corner] => ((0, 0), corner),
+                [first, second] => (first, second),
+                _ => return Err("expected at most two corners".to_string())
+            };
+
+            let top_left = (first.0.min(second.0), first.1.min(second.1));
+            let bottom_right = (first.0.max(second.0), first.1.max(second.1));
+            Ok(cave.risk_level(top_left, bottom_right).to_string())
+        }
+        "route" => {
+            let rescue = cave.rescue()?;
+            let mut lines: Vec<String> = rescue.steps.iter()
+                .map(|step| match step.action {
+                    Action::Move((x, y)) => format!("{:>5}: move to {},{}", step.minute, x, y),
+                    Action::Switch(tool) => format!("{:>5}: switch to {}", step.minute, tool.name()),
+                })
+                .collect();
+
+            lines.push(format!("reached the target after {} minutes", rescue.minutes));
+            Ok(lines.join("\n"))
+        }
+        "map" => {
+            let rescue = cave.rescue()?;
+            let bottom_right = match args {
+                [] => rescue.positions().iter().fold(target, |(x, y), &(pos, _)| (x.max(pos.0), y.max(pos.1))),
+                [corner] => parse_coordinate(corner)?,
+                _ => return Err("expected at most one corner".to_string())
+            };
+
+            Ok(cave.render(bottom_right, &rescue))
+        }
+        _ => Result::Err(format!("Unknown mode `{}`, expected risk, route or map", mode))
+    }
+}
@@ -1,60 +1,15 @@
-use std::collections::HashSet;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
 use std::io::BufRead;
-use std::iter::FromIterator;
-use std::thread;
-use std::time::Duration;
 
-use rayon::prelude::*;
 use regex::Regex;
 
 use utils;
 
 type Position = (i32, i32, i32);
 
-trait VectorLike {
-    fn get_average<T: Iterator<Item=Position>>(positions: T) -> Option<Position>;
-    fn length(&self) -> u32;
-    fn direction(&self) -> Position;
-}
-
-impl VectorLike for Position {
-    fn get_average<T: Iterator<Item=Position>>(positions: T) -> Option<Position> {
-        let mut sum_position = (0, 0, 0);
-        let mut len = 0;
-
-        for pos in positions {
-            sum_position.0 += pos.0 as i64;
-            sum_position.1 += pos.1 as i64;
-            sum_position.2 += pos.2 as i64;
-            len += 1;
-        }
-        if len > 0 {
-            Some(((sum_position.0 / len) as i32, (sum_position.1 / len) as i32, (sum_position.2 / len) as i32))
-        } else {
-            None
-        }
-    }
-
-    //noinspection RsUnresolvedReference
-    fn length(&self) -> u32 {
-        (self.0.abs() + self.1.abs() + self.2.abs()) as u32
-    }
-
-    //noinspection RsUnresolvedReference
-    fn direction(&self) -> Position {
-        let lengths = [self.0.abs(), self.1.abs(), self.2.abs()];
-
-        let (index, _) = lengths.iter().enumerate()
-            .max_by(|(_, a), (_, b)| a.cmp(&b))
-            .unwrap();
-
-        match index {
-            0 => (self.0.signum(), 0, 0),
-            1 => (0, self.1.signum(), 0),
-            2 => (0, 0, self.2.signum()),
-            _ => unreachable!(),
-        }
-    }
+fn manhattan(a: &Position, b: &Position) -> u32 {
+    ((a.0 as i64 - b.0 as i64).abs() + (a.1 as i64 - b.1 as i64).abs() + (a.2 as i64 - b.2 as i64).abs()) as u32
 }
 
 #[derive(Debug, Eq, Hash, PartialEq)]
@@ -77,12 +32,8 @@ impl Nanobot {
 
         Some(Nanobot { position: (x, y, z), radius })
     }
-    //noinspection RsUnresolvedReference
     fn distance_to_point(&self, point: &Position) -> u32 {
-        let (sx, sy, sz) = self.position;
-        let (ox, oy, oz) = point;
-
-        (sx - ox, sy - oy, sz - oz).length()
+        manhattan(&self.position, point)
     }
 
     fn distance_to(&self, other: &Nanobot) -> u32 {
@@ -111,110 +62,653 @@ fn get_nanobots<T: BufRead>(input: T) -> Vec<Nanobot> {
         .collect()
 }
 
+/// A node of the swarm's k-d tree, covering the bots `order[start..end]`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Node {
+    start: usize,
+    end: usize,
+    /// Corners of the box around the positions of the node's bots.
+    min: Position,
+    max: Position,
+    /// The largest range of the node's bots.
+    radius: u32,
+    children: Option<(usize, usize)>,
+}
+
+impl Node {
+    /// The distance from the point to the closest position in the node's box.
+    fn distance_to(&self, point: &Position) -> u64 {
+        let gap = |min: i32, max: i32, value: i32| (min as i64 - value as i64).max(value as i64 - max as i64).max(0);
+        (gap(self.min.0, self.max.0, point.0) + gap(self.min.1, self.max.1, point.1) + gap(self.min.2, self.max.2, point.2)) as u64
+    }
+}
+
+/// The bots in a k-d tree over their positions.
+///
+/// Every node also remembers the largest range among its bots, so a query only has to look at the
+/// bots of the nodes that come close enough to possibly match.
+struct Swarm {
+    bots: Vec<Nanobot>,
+    /// Indices into `bots`, ordered so that the bots of every node are next to each other.
+    order: Vec<usize>,
+    nodes: Vec<Node>,
+}
+
+impl Swarm {
+    /// Nodes with at most this many bots aren't split any further.
+    const LEAF_SIZE: usize = 8;
+
+    fn new(bots: Vec<Nanobot>) -> Swarm {
+        let mut swarm = Swarm { order: (0..bots.len()).collect(), bots, nodes: Vec::new() };
+        if !swarm.bots.is_empty() {
+            let end = swarm.bots.len();
+            swarm.build(0, end);
+        }
+
+        swarm
+    }
+
+    fn build(&mut self, start: usize, end: usize) -> usize {
+        let bots = &self.bots;
+        let positions = || self.order[start..end].iter().map(|&index| bots[index].position);
+
+        let min = positions().fold((i32::MAX, i32::MAX, i32::MAX), |min, pos| (min.0.min(pos.0), min.1.min(pos.1), min.2.min(pos.2)));
+        let max = positions().fold((i32::MIN, i32::MIN, i32::MIN), |max, pos| (max.0.max(pos.0), max.1.max(pos.1), max.2.max(pos.2)));
+        let radius = self.order[start..end].iter().map(|&index| bots[index].radius).max().unwrap();
+
+        let index = self.nodes.len();
+        self.nodes.push(Node { start, end, min, max, radius, children: None });
+        if end - start <= Swarm::LEAF_SIZE {
+            return index;
+        }
+
+        // split along the axis the bots are spread out the most on
+        let spread = [max.0 as i64 - min.0 as i64, max.1 as i64 - min.1 as i64, max.2 as i64 - min.2 as i64];
+        let axis = (0..3).max_by_key(|&axis| spread[axis]).unwrap();
+        let coordinate = |bot: &Nanobot| match axis {
+            0 => bot.position.0,
+            1 => bot.position.1,
+            _ => bot.position.2,
+        };
+        self.order[start..end].sort_by_key(|&index| coordinate(&bots[index]));
+
+        let middle = (start + end) / 2;
+        let below = self.build(start, middle);
+        let above = self.build(middle, end);
+        self.nodes[index].children = Some((below, above));
+        index
+    }
+
+    /// The bots `accept` agrees with, in input order. `could_match` tells whether a node is close
+    /// enough for that to be possible for any of its bots.
+    fn search<F, G>(&self, could_match: F, accept: G) -> Vec<usize>
+        where F: Fn(&Node) -> bool, G: Fn(&Nanobot) -> bool {
+        let mut found = Vec::new();
+        let mut stack = if self.nodes.is_empty() { Vec::new() } else { vec![0] };
+
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if !could_match(node) {
+                continue;
+            }
+
+            match node.children {
+                Some((below, above)) => stack.extend(&[below, above]),
+                None => found.extend(self.order[node.start..node.end].iter().filter(|&&bot| accept(&self.bots[bot]))),
+            }
+        }
+
+        found.sort();
+        found
+    }
+
+    /// The bots that have the point in range.
+    fn covering(&self, point: &Position) -> Vec<usize> {
+        self.search(
+            |node| node.distance_to(point) <= node.radius as u64,
+            |bot| bot.point_in_range(point),
+        )
+    }
+
+    /// The bots in range of the bot, including itself.
+    fn in_range_of(&self, bot: &Nanobot) -> Vec<usize> {
+        self.search(
+            |node| node.distance_to(&bot.position) <= bot.radius as u64,
+            |other| bot.other_in_range(other),
+        )
+    }
+
+    /// The bots whose range overlaps with the bot's, including itself.
+    fn intersecting(&self, bot: &Nanobot) -> Vec<usize> {
+        self.search(
+            |node| node.distance_to(&bot.position) <= bot.radius as u64 + node.radius as u64,
+            |other| bot.range_intersects(other),
+        )
+    }
+}
+
 pub fn solve_first<T: BufRead>(input: T) -> u32 {
-    let bots = get_nanobots(input);
+    let swarm = Swarm::new(get_nanobots(input));
 
-    let master = bots.iter()
+    let master = swarm.bots.iter()
         .max_by(|a, b| a.radius.cmp(&b.radius))
         .expect("where da master at?");
 
-    bots.iter()
-        .filter(|bot| master.other_in_range(bot))
-        .count() as u32
+    swarm.in_range_of(master).len() as u32
 }
 
-pub fn solve_second<T: BufRead>(input: T) -> u32 {
-    let bots = get_nanobots(input);
+type Range = (i64, i64);
+
+fn floor_half(value: i64) -> i64 {
+    value.div_euclid(2)
+}
+
+fn ceil_half(value: i64) -> i64 {
+    -(-value).div_euclid(2)
+}
+
+fn intersect(a: Range, b: Range) -> Option<Range> {
+    Some((a.0.max(b.0), a.1.min(b.1))).filter(|range| range.0 <= range.1)
+}
+
+/// Smallest absolute value of `2v + offset` for any `v` in the range.
+fn min_abs(range: Range, offset: i64) -> i64 {
+    let (low, high) = (2 * range.0 + offset, 2 * range.1 + offset);
+    if low <= 0 && 0 <= high { 0 } else { low.abs().min(high.abs()) }
+}
+
+/// The search doesn't happen in x, y and z, but in `q = x + y - z`, `s = x - y + z` and
+/// `t = -x + y + z`, with `p = x + y + z = q + s + t`. A bot's range is then simply a range of
+/// values for each of q, s, t and p, and the distance to the origin is the largest of their
+/// absolute values.
+///
+/// q, s and t of a point with integer coordinates are either all even or all odd. So every point
+/// belongs to one of two parities `e` and is written as `q = 2q' + e` and so on, which makes the
+/// q', s' and t' of all points with that parity exactly the integers.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Rotated {
+    /// Which bot this is.
+    index: usize,
+    /// Ranges of q', s' and t'.
+    axes: [Range; 3],
+    /// Range of `p' = q' + s' + t'`.
+    sum: Range,
+}
+
+impl Rotated {
+    fn new(index: usize, bot: &Nanobot, parity: i64) -> Option<Rotated> {
+        let (x, y, z) = (bot.position.0 as i64, bot.position.1 as i64, bot.position.2 as i64);
+        let r = bot.radius as i64;
+        let halve = |center: i64, offset: i64| (ceil_half(center - r - offset), floor_half(center + r - offset));
+
+        let axes = [halve(x + y - z, parity), halve(x - y + z, parity), halve(-x + y + z, parity)];
+        let sum = halve(x + y + z, 3 * parity);
+
+        if axes.iter().chain(Some(&sum)).any(|range| range.0 > range.1) {
+            return None;
+        }
+
+        Some(Rotated { index, axes, sum })
+    }
+}
+
+/// A box of q', s' and t' values of one parity.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Cell {
+    parity: i64,
+    axes: [Range; 3],
+}
+
+impl Cell {
+    /// Range of p' over all the points of the cell.
+    fn sum(&self) -> Range {
+        (self.axes.iter().map(|axis| axis.0).sum(), self.axes.iter().map(|axis| axis.1).sum())
+    }
+
+    /// Whether some point in the cell is in range of the bot.
+    fn reaches(&self, bot: &Rotated) -> bool {
+        self.axes.iter().zip(bot.axes.iter()).all(|(&axis, &other)| intersect(axis, other).is_some())
+            && intersect(self.sum(), bot.sum).is_some()
+    }
+
+    /// The bots of the cell's parity that have some point of it in range.
+    fn reaching<'a>(&self, rotated: &'a [Vec<Rotated>; 2]) -> Vec<&'a Rotated> {
+        rotated[self.parity as usize].iter().filter(|bot| self.reaches(bot)).collect()
+    }
+
+    /// Whether the bot's q', s' and t' ranges either cover the whole cell or nothing of it, which
+    /// leaves only p' to tell whether a point is in range.
+    fn is_resolved_by(&self, bot: &Rotated) -> bool {
+        self.axes.iter().zip(bot.axes.iter())
+            .all(|(&axis, &other)| other.0 <= axis.0 && axis.1 <= other.1)
+    }
+
+    /// No point in the cell is closer to the origin than this.
+    fn min_distance(&self) -> i64 {
+        let sum = min_abs(self.sum(), 3 * self.parity);
+        self.axes.iter().map(|&axis| min_abs(axis, self.parity)).max().unwrap().max(sum)
+    }
 
-    let mut intersection_groups: Vec<HashSet<Nanobot>> = Vec::new();
-    for bot in bots {
-        {
-            let mut target_group = intersection_groups.par_iter_mut()
-                .find_first(|group|
-                    group.par_iter().all(|other| bot.range_intersects(other)));
+    /// Splits the cell at the middle one of the edges of bot ranges running through it, along the
+    /// axis with the most of them.
+    fn split(&self, bots: &[&Rotated]) -> (Cell, Cell) {
+        let (axis, mut cuts) = (0..3)
+            .map(|axis| {
+                let (low, high) = self.axes[axis];
+                let cuts: Vec<i64> = bots.iter()
+                    .flat_map(|bot| vec![bot.axes[axis].0, bot.axes[axis].1 + 1])
+                    .filter(|&cut| low < cut && cut <= high)
+                    .collect();
+                (axis, cuts)
+            })
+            .max_by_key(|(_, cuts)| cuts.len())
+            .unwrap();
+
+        cuts.sort();
+        let cut = cuts[cuts.len() / 2];
 
-            if let Some(group) = target_group {
-                group.insert(bot);
+        let (mut below, mut above) = (*self, *self);
+        below.axes[axis].1 = cut - 1;
+        above.axes[axis].0 = cut;
+        (below, above)
+    }
+
+    /// The point with the most bots in range in a cell that no bot's q', s' and t' ranges cut
+    /// through, and the closest of those to the origin. `bots` are the ones reaching the cell.
+    fn best_point(&self, bots: &[&Rotated]) -> (usize, i64, Position) {
+        let cell_sum = self.sum();
+
+        // sweep along p' to find where the most ranges overlap
+        let mut events: Vec<(i64, i64)> = bots.iter()
+            .map(|bot| intersect(bot.sum, cell_sum).unwrap())
+            .flat_map(|(low, high)| vec![(low, 1), (high + 1, -1)])
+            .collect();
+        events.sort();
+
+        let (mut count, mut most) = (0, 0);
+        let mut best_ranges: Vec<Range> = Vec::new();
+        for (index, &(value, change)) in events.iter().enumerate() {
+            count += change;
+            let end = events.get(index + 1).map_or(value, |next| next.0 - 1);
+            if end < value || count < most {
                 continue;
             }
+
+            if count > most {
+                most = count;
+                best_ranges.clear();
+            }
+            best_ranges.push((value, end));
         }
 
-        intersection_groups.push(HashSet::from_iter(vec![bot]));
+        let (distance, position) = self.closest(&best_ranges);
+        (most as usize, distance, position)
     }
 
-    let most: &HashSet<Nanobot> = intersection_groups.par_iter()
-        .max_by(|a, b| a.len().cmp(&b.len()))
-        .expect("no intersections");
+    /// Splits the cell's p' range into pieces with the same bots in range, for a cell no bot's q',
+    /// s' and t' ranges cut through, and lists the bots of each piece.
+    fn segments(&self, bots: &[&Rotated]) -> Vec<(Range, Vec<usize>)> {
+        let cell_sum = self.sum();
+        let mut edges: Vec<i64> = bots.iter()
+            .map(|bot| intersect(bot.sum, cell_sum).unwrap())
+            .flat_map(|(low, high)| vec![low, high + 1])
+            .collect();
+        edges.sort();
+        edges.dedup();
+
+        edges.windows(2)
+            .map(|edges| (edges[0], edges[1] - 1))
+            .map(|range| {
+                let inside = bots.iter()
+                    .filter(|bot| bot.sum.0 <= range.0 && range.1 <= bot.sum.1)
+                    .map(|bot| bot.index)
+                    .collect();
+                (range, inside)
+            })
+            .filter(|(_, inside): &(Range, Vec<usize>)| !inside.is_empty())
+            .collect()
+    }
 
-    let mut point: Position = (0, 0, 0);
-    let mut step_factors = [0; 3];
-    let mut step_signs = [0; 3];
+    /// The point closest to the origin whose p' is in one of the ranges, along with its distance.
+    /// The ranges have to lie within the cell's p' range.
+    fn closest(&self, sums: &[Range]) -> (i64, Position) {
+        let parity = self.parity;
+        // q', s', t' and p' values of points no further than `distance` from the origin
+        let within = |range: Range, offset: i64, distance: i64| {
+            intersect(range, (ceil_half(-distance - offset), floor_half(distance - offset)))
+        };
+        let point_within = |distance: i64| {
+            let axes = [
+                within(self.axes[0], parity, distance)?,
+                within(self.axes[1], parity, distance)?,
+                within(self.axes[2], parity, distance)?,
+            ];
+            let reachable = (axes.iter().map(|axis| axis.0).sum(), axes.iter().map(|axis| axis.1).sum());
+
+            sums.iter()
+                .filter_map(|&range| intersect(range, reachable))
+                .filter_map(|range| within(range, 3 * parity, distance))
+                .next()
+                .map(|sum| (axes, sum.0))
+        };
+
+        let (mut low, mut high) = (0, 1 << 40);
+        while low < high {
+            let middle = (low + high) / 2;
+            if point_within(middle).is_some() { high = middle; } else { low = middle + 1; }
+        }
+
+        // spread p' over q', s' and t' starting from their lowest values
+        let (axes, sum) = point_within(low).unwrap();
+        let mut rest = sum - axes.iter().map(|axis| axis.0).sum::<i64>();
+        let mut values = [0; 3];
+        for (value, axis) in values.iter_mut().zip(axes.iter()) {
+            let extra = rest.min(axis.1 - axis.0);
+            *value = 2 * (axis.0 + extra) + parity;
+            rest -= extra;
+        }
 
-    static DEBUG: bool = false;
+        let [q, s, t] = values;
+        let position = (((q + s) / 2) as i32, ((q + t) / 2) as i32, ((s + t) / 2) as i32);
+        (low, position)
+    }
+}
+
+/// The point in range of the most bots, closest to the origin if there are several.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct BestPoint {
+    position: Position,
+    bots: usize,
+    distance: u32,
+}
 
-    let mut get_step_factor = |step_factors: &mut [i32; 3], i: usize, sign: i32| {
-        if sign == step_signs[i] {
-            step_factors[i] += 1;
+/// q', s' and t' ranges of the bots, split by parity.
+fn rotate<'a, I>(bots: I) -> [Vec<Rotated>; 2] where I: Iterator<Item=(usize, &'a Nanobot)> + Clone {
+    [
+        bots.clone().filter_map(|(index, bot)| Rotated::new(index, bot, 0)).collect(),
+        bots.filter_map(|(index, bot)| Rotated::new(index, bot, 1)).collect(),
+    ]
+}
+
+/// The cells the searches start with: one for each parity, just big enough for all bots.
+fn bounding_cells(rotated: &[Vec<Rotated>; 2]) -> Vec<Cell> {
+    (0..2)
+        .filter(|&parity| !rotated[parity].is_empty())
+        .map(|parity| {
+            let mut axes = [(i64::MAX, i64::MIN); 3];
+            for bot in rotated[parity].iter() {
+                for (axis, other) in axes.iter_mut().zip(bot.axes.iter()) {
+                    *axis = (axis.0.min(other.0), axis.1.max(other.1));
+                }
+            }
+
+            Cell { parity: parity as i64, axes }
+        })
+        .collect()
+}
+
+/// Finds the best point with a best-first search over cells, which start out covering all bots
+/// and are split at the edges of bot ranges.
+///
+/// The number of bots reaching into a cell is an upper bound for every point in it and its
+/// distance a lower bound, so looking at the cells with the most bots first (then the ones closest
+/// to the origin) means that the first exact answer taken off the queue is the best one. A cell
+/// no bot range cuts through gets an exact answer straight away, as there are no more edges to
+/// split it at.
+fn find_best_point(bots: &[Nanobot]) -> Option<BestPoint> {
+    let rotated = rotate(bots.iter().enumerate());
+
+    let mut cells: Vec<(Cell, Option<Position>)> = Vec::new();
+    let mut queue = BinaryHeap::new();
+
+    let push = |cells: &mut Vec<(Cell, Option<Position>)>, queue: &mut BinaryHeap<_>, cell: Cell| {
+        let reaching = cell.reaching(&rotated);
+        if reaching.is_empty() {
+            return;
+        }
+
+        let (count, distance, position) = if reaching.iter().all(|bot| cell.is_resolved_by(bot)) {
+            let (count, distance, position) = cell.best_point(&reaching);
+            (count, distance, Some(position))
         } else {
-            step_factors[i] = 0;
-            step_signs[i] = sign;
+            (reaching.len(), cell.min_distance(), None)
+        };
+
+        queue.push((count, Reverse(distance), position.is_some(), cells.len()));
+        cells.push((cell, position));
+    };
+
+    for cell in bounding_cells(&rotated) {
+        push(&mut cells, &mut queue, cell);
+    }
+
+    while let Some((count, Reverse(distance), exact, index)) = queue.pop() {
+        let (cell, position) = cells[index];
+        if exact {
+            return Some(BestPoint { position: position.unwrap(), bots: count, distance: distance as u32 });
         }
 
-        step_factors[i]
+        let (below, above) = cell.split(&cell.reaching(&rotated));
+        push(&mut cells, &mut queue, below);
+        push(&mut cells, &mut queue, above);
+    }
+
+    None
+}
+
+/// Bots that all have a common point in range, along with the closest such point to the origin.
+#[derive(Clone, Debug, PartialEq)]
+struct Region {
+    bots: Vec<usize>,
+    position: Position,
+    distance: u32,
+}
+
+/// Finds the `limit` biggest groups of at least `at_least` bots whose ranges have points in
+/// common, closest first among groups of the same size. Groups that are part of a larger one are
+/// left out.
+///
+/// This is the same search over cells as for the best point, except that it goes on after the
+/// first answer. Once no cell left in the queue reaches as many bots as the biggest group found so
+/// far, every group of that size has turned up. Bots whose range doesn't even overlap with enough
+/// other ones can't be part of a group, so they're dropped up front.
+fn find_regions(swarm: &Swarm, at_least: usize, limit: usize) -> Vec<Region> {
+    let at_least = at_least.max(1);
+    let candidates = swarm.bots.iter().enumerate()
+        .filter(|&(_, bot)| swarm.intersecting(bot).len() >= at_least);
+    let rotated = rotate(candidates);
+
+    let mut cells: Vec<Cell> = Vec::new();
+    let mut queue = BinaryHeap::new();
+    let push = |cells: &mut Vec<Cell>, queue: &mut BinaryHeap<(usize, usize)>, cell: Cell| {
+        let reaching = cell.reaching(&rotated).len();
+        if reaching >= at_least {
+            queue.push((reaching, cells.len()));
+            cells.push(cell);
+        }
     };
 
-    loop {
-        let mut out_of_range = HashSet::new();
+    for cell in bounding_cells(&rotated) {
+        push(&mut cells, &mut queue, cell);
+    }
+
+    // a group inside one that's already listed can't lead to a new one
+    let is_listed = |bots: &[usize], regions: &[Region]| {
+        regions.iter().any(|region| bots.iter().all(|bot| region.bots.binary_search(bot).is_ok()))
+    };
 
-        for bot in most.iter() {
-            if !bot.point_in_range(&point) {
-                out_of_range.insert(bot.position);
+    let mut groups: HashMap<Vec<usize>, (i64, Position)> = HashMap::new();
+    let mut regions = Vec::new();
+    while regions.len() < limit {
+        let bound = queue.peek().map_or(0, |&(reaching, _)| reaching);
+        let size = groups.keys().map(|bots| bots.len()).max().filter(|&size| size > bound);
+
+        if let Some(size) = size {
+            let mut done: Vec<(Vec<usize>, (i64, Position))> = groups.iter()
+                .filter(|&(bots, _)| bots.len() == size)
+                .map(|(bots, &closest)| (bots.clone(), closest))
+                .collect();
+            done.sort_by(|a, b| ((a.1).0, &a.0).cmp(&((b.1).0, &b.0)));
+
+            for (bots, (distance, position)) in done {
+                groups.remove(&bots);
+                if regions.len() < limit && !is_listed(&bots, &regions) {
+                    regions.push(Region { bots, position, distance: distance as u32 });
+                }
             }
-        }
 
-        if out_of_range.is_empty() {
-            break;
+            continue;
         }
 
-        let avg = Position::get_average(out_of_range.iter()
-            .map(|pos| pos.clone())).unwrap();
+        let cell = match queue.pop() {
+            Some((_, index)) => cells[index],
+            None => break
+        };
 
-        let direction = (avg.0 - point.0, avg.1 - point.1, avg.2 - point.2);
-        let dir_norm = direction.direction();
+        let reaching = cell.reaching(&rotated);
+        if is_listed(&reaching.iter().map(|bot| bot.index).collect::<Vec<usize>>(), &regions) {
+            continue;
+        }
 
-        let step_factor;
-        if dir_norm.0 != 0 {
-            step_factor = get_step_factor(&mut step_factors, 0, dir_norm.0);
-        } else if dir_norm.1 != 0 {
-            step_factor = get_step_factor(&mut step_factors, 1, dir_norm.1);
-        } else if dir_norm.2 != 0 {
-            step_factor = get_step_factor(&mut step_factors, 2, dir_norm.2);
-        } else {
-            unreachable!();
+        if !reaching.iter().all(|bot| cell.is_resolved_by(bot)) {
+            let (below, above) = cell.split(&reaching);
+            push(&mut cells, &mut queue, below);
+            push(&mut cells, &mut queue, above);
+            continue;
         }
 
-        let step = 1.5f32.powf(step_factor as f32) as i32;
-        point.0 += dir_norm.0 * step;
-        point.1 += dir_norm.1 * step;
-        point.2 += dir_norm.2 * step;
+        for (range, bots) in cell.segments(&reaching) {
+            if bots.len() < at_least || is_listed(&bots, &regions) {
+                continue;
+            }
 
-        if DEBUG {
-            println!("missing {}", out_of_range.len());
-            println!("target {:?}", avg);
-            println!("factors {:?}", step_factors);
-            println!("direction {:?} -> {}x", dir_norm, step);
-            println!("new pos: {:?}\n", point);
-            thread::sleep(Duration::from_millis(25));
+            let closest = cell.closest(&[range]);
+            let group = groups.entry(bots).or_insert(closest);
+            if closest.0 < group.0 {
+                *group = closest;
+            }
         }
     }
 
-    point.length()
+    regions
+}
+
+pub fn solve_second<T: BufRead>(input: T) -> Result<u32, String> {
+    let bots = get_nanobots(input);
+    find_best_point(&bots)
+        .map(|best| best.distance)
+        .ok_or_else(|| "There are no nanobots".to_string())
 }
 
 pub fn solve<T: BufRead>(problem: u8, input: T) -> Result<String, String> {
     match problem {
         1 => Result::Ok(solve_first(input).to_string()),
-        2 => Result::Ok(solve_second(input).to_string()),
+        2 => solve_second(input).map(|result| result.to_string()),
         _ => Result::Err("This problem only has 2 parts!".to_string())
     }
-}
\ No newline at end of file
+}
+
+/// How many groups `regions` shows by default, there can be a lot of them.
+const REGION_LIMIT: usize = 10;
+
+fn parse_position(arg: &str) -> Result<Position, String> {
+    let coordinates: Vec<i32> = arg.split(',')
+        .map(|coordinate| coordinate.trim().parse())
+        .collect::<Result<_, _>>()
+        .map_err(|_| format!("invalid position `{}`, expected `x,y,z`", arg))?;
+
+    match coordinates[..] {
+        [x, y, z] => Ok((x, y, z)),
+        _ => Err(format!("invalid position `{}`, expected `x,y,z`", arg))
+    }
+}
+
+/// Bots are numbered by the line they're on, starting at 1.
+fn parse_bot(arg: &str, swarm: &Swarm) -> Result<usize, String> {
+    match arg.trim().parse::<usize>() {
+        Ok(number) if 1 <= number && number <= swarm.bots.len() => Ok(number - 1),
+        _ => Err(format!("invalid bot `{}`, expected a number from 1 to {}", arg, swarm.bots.len()))
+    }
+}
+
+fn list_bots(bots: &[usize]) -> String {
+    bots.iter().map(|bot| (bot + 1).to_string()).collect::<Vec<String>>().join(", ")
+}
+
+/// `best` shows the point in range of the most nanobots (the one closest to the origin if there
+/// are several), how many bots reach it and its distance from the origin.
+///
+/// The queries number the bots by their line. `covering <x,y,z...>` counts the bots that have a
+/// point in range, `in-range <bot...>` lists the bots in range of a bot and `overlapping <bot...>`
+/// the bots whose range overlaps with its own. `regions <count> [limit]` lists the biggest groups
+/// of at least that many bots with a point in range of all of them (10 unless a limit is given),
+/// with the point closest to the origin.
+pub fn run_mode<T: BufRead>(mode: &str, args: &[&str], input: T) -> Result<String, String> {
+    let swarm = Swarm::new(get_nanobots(input));
+    let bots = |args: &[&str]| args.iter().map(|arg| parse_bot(arg, &swarm)).collect::<Result<Vec<usize>, String>>();
+
+    match mode {
+        "best" => {
+            let best = find_best_point(&swarm.bots).ok_or("There are no nanobots")?;
+            let (x, y, z) = best.position;
+            Ok(format!("{},{},{} is in range of {} bots and {} away from the origin", x, y, z, best.bots, best.distance))
+        }
+        "covering" => {
+            let points = args.iter().map(|arg| parse_position(arg)).collect::<Result<Vec<Position>, String>>()?;
+            let lines: Vec<String> = points.iter()
+                .map(|point| format!("{},{},{} is in range of {} bots", point.0, point.1, point.2, swarm.covering(point).len()))
+                .collect();
+
+            Ok(lines.join("\n"))
+        }
+        "in-range" => {
+            let lines: Vec<String> = bots(args)?.into_iter()
+                .map(|bot| {
+                    let in_range = swarm.in_range_of(&swarm.bots[bot]);
+                    format!("{} bots are in range of bot {}: {}", in_range.len(), bot + 1, list_bots(&in_range))
+                })
+                .collect();
+
+            Ok(lines.join("\n"))
+        }
+        "overlapping" => {
+            let lines: Vec<String> = bots(args)?.into_iter()
+                .map(|bot| {
+                    let others: Vec<usize> = swarm.intersecting(&swarm.bots[bot]).into_iter().filter(|&other| other != bot).collect();
+                    format!("{} bots overlap with the range of bot {}: {}", others.len(), bot + 1, list_bots(&others))
+                })
+                .collect();
+
+            Ok(lines.join("\n"))
+        }
+        "regions" => {
+            let number = |arg: &str| arg.parse::<usize>().map_err(|_| format!("invalid number `{}`", arg));
+            let (at_least, limit) = match args {
+                [count] => (number(count)?, REGION_LIMIT),
+                [count, limit] => (number(count)?, number(limit)?),
+                _ => return Err("expected the number of bots a region needs and optionally how many to show".to_string())
+            };
+            if limit == 0 {
+                return Err("the number of regions to show has to be at least 1".to_string());
+            }
+
+            let regions = find_regions(&swarm, at_least, limit);
+            if regions.is_empty() {
+                return Err(format!("No point is in range of {} bots", at_least));
+            }
+
+            let lines: Vec<String> = regions.iter()
+                .map(|region| {
+                    let (x, y, z) = region.position;
+                    format!(
+                        "{} bots meet at {},{},{}, {} away from the origin: {}",
+                        region.bots.len(), x, y, z, region.distance, list_bots(&region.bots)
+                    )
+                })
+                .collect();
+
+            Ok(lines.join("\n"))
+        }
+        _ => Result::Err(format!("Unknown mode `{}`, expected best, covering, in-range, overlapping or regions", mode))
+    }
+}
@@ -55,4 +55,22 @@ pub fn solve<T>(day: u8, part: u8, input: T) -> Result<String, String> where T:
         25 => day25::solve(part, input),
         _ => Result::Err(format!("Day {} doesn't exist. (1 - 25)", day))
     }
+}
+
+/// Runs one of the additional tools a day offers, e.g. the ElfCode assembler of day 19.
+pub fn run_mode<T>(day: u8, mode: &str, args: &[&str], input: T) -> Result<String, String> where T: BufRead {
+    match day {
+        12 => day12::run_mode(mode, args, input),
+        13 => day13::run_mode(mode, args, input),
+        15 => day15::run_mode(mode, args, input),
+        16 => day16::run_mode(mode, args, input),
+        17 => day17::run_mode(mode, args, input),
+        18 => day18::run_mode(mode, args, input),
+        19 => day19::run_mode(mode, args, input),
+        20 => day20::run_mode(mode, args, input),
+        21 => day21::run_mode(mode, args, input),
+        22 => day22::run_mode(mode, args, input),
+        23 => day23::run_mode(mode, args, input),
+        _ => Result::Err(format!("Day {} doesn't have any modes.", day))
+    }
 }
\ No newline at end of file